@@ -0,0 +1,322 @@
+use {Jitter, Sleep, Timer, TimerError};
+use interval::{from_nanos, nanos};
+
+use futures::{Future, IntoFuture, Stream, Async, Poll};
+
+use std::{fmt, mem};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Configures the delays of a `Backoff`
+///
+/// The first delay is `initial`. Each following delay is the previous one
+/// multiplied by the multiplier, up to `max_delay`. Jitter, if any, is applied
+/// to each delay after capping it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffPolicy {
+    initial: Duration,
+    multiplier: f64,
+    max_delay: Option<Duration>,
+    max_elapsed: Option<Duration>,
+    jitter: Option<Jitter>,
+}
+
+/// A stream yielding after exponentially growing delays
+///
+/// Created by `Timer::backoff`. The stream ends once the next delay would
+/// complete after the `max_elapsed` budget of its policy.
+#[derive(Debug)]
+pub struct Backoff {
+    policy: BackoffPolicy,
+    sleep: Sleep,
+    // The next delay, before jitter
+    delay: Duration,
+    // The instant the budget is measured from
+    start: Instant,
+    // True while `sleep` is waiting for a delay
+    armed: bool,
+}
+
+/// Retries an operation according to a `BackoffPolicy`
+///
+/// Created by `Timer::retry`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Retry<F, R: IntoFuture> {
+    factory: F,
+    backoff: Backoff,
+    state: State<R::Future, R::Error>,
+}
+
+enum State<T, E> {
+    Running(T),
+    Waiting(E),
+    Done,
+}
+
+/// The error type of `Retry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryError<E> {
+    /// The retry budget is exhausted. Contains the last error returned by the
+    /// operation.
+    Exhausted(E),
+    /// An error caused by the timer while waiting to retry. Contains the last
+    /// error returned by the operation.
+    Timer(E, TimerError),
+}
+
+/// Create a new backoff
+pub fn new(timer: &Timer, policy: BackoffPolicy) -> Backoff {
+    let now = timer.now();
+
+    Backoff {
+        policy: policy,
+        sleep: timer.sleep_until(now),
+        delay: policy.initial,
+        start: now,
+        armed: false,
+    }
+}
+
+/// Create a new retry future, running the operation for the first time
+pub fn retry<F, R>(timer: &Timer, mut factory: F, policy: BackoffPolicy) -> Retry<F, R>
+    where F: FnMut() -> R,
+          R: IntoFuture,
+{
+    let future = factory().into_future();
+
+    Retry {
+        factory: factory,
+        backoff: new(timer, policy),
+        state: State::Running(future),
+    }
+}
+
+/*
+ *
+ * ===== BackoffPolicy =====
+ *
+ */
+
+impl BackoffPolicy {
+    /// Create a policy starting with a delay of `initial`, doubling it after
+    /// each attempt, without limits and without jitter.
+    pub fn new(initial: Duration) -> BackoffPolicy {
+        BackoffPolicy {
+            initial: initial,
+            multiplier: 2.0,
+            max_delay: None,
+            max_elapsed: None,
+            jitter: None,
+        }
+    }
+
+    /// Set the factor applied to the delay after each attempt.
+    ///
+    /// Defaults to 2.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Set the longest delay between two attempts.
+    ///
+    /// Defaults to no limit.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Set the total time budget, measured from the creation of the
+    /// `Backoff`. No delay completing after the budget is started.
+    ///
+    /// Defaults to no limit.
+    pub fn max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// Set the jitter applied to each delay.
+    ///
+    /// Defaults to no jitter.
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    /// Returns the delay following `delay`
+    fn next(&self, delay: Duration) -> Duration {
+        let next = nanos(delay) as f64 * self.multiplier;
+
+        // Not greater than zero also covers a NaN multiplier
+        let next = if !(next > 0.0) {
+            Duration::from_millis(0)
+        } else if next >= u64::max_value() as f64 {
+            from_nanos(u64::max_value())
+        } else {
+            from_nanos(next as u64)
+        };
+
+        self.cap(next)
+    }
+
+    fn cap(&self, delay: Duration) -> Duration {
+        match self.max_delay {
+            Some(max) if delay > max => max,
+            _ => delay,
+        }
+    }
+}
+
+/*
+ *
+ * ===== Backoff =====
+ *
+ */
+
+impl Backoff {
+    /// Returns the policy of the backoff
+    pub fn policy(&self) -> &BackoffPolicy {
+        &self.policy
+    }
+
+    /// Restart the backoff from the initial delay, typically after a
+    /// successful attempt. The `max_elapsed` budget is measured from now.
+    pub fn reset(&mut self) {
+        self.delay = self.policy.initial;
+        self.start = self.sleep.timer().now();
+        self.armed = false;
+    }
+}
+
+impl Stream for Backoff {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Option<()>, TimerError> {
+        if !self.armed {
+            let timer = self.sleep.timer().clone();
+            let delay = self.policy.cap(self.delay);
+
+            let delay = match self.policy.jitter {
+                Some(ref jitter) => ::timer::jitter(&timer, delay, jitter),
+                None => delay,
+            };
+
+            let now = timer.now();
+
+            if let Some(max) = self.policy.max_elapsed {
+                // Compare durations rather than deadlines, which saturate
+                let elapsed = nanos(now - self.start).saturating_add(nanos(delay));
+
+                if elapsed > nanos(max) {
+                    return Ok(Async::Ready(None));
+                }
+            }
+
+            self.sleep.reset(::timer::deadline(now, delay));
+            self.delay = self.policy.next(self.delay);
+            self.armed = true;
+        }
+
+        try_ready!(self.sleep.poll());
+
+        self.armed = false;
+        Ok(Async::Ready(Some(())))
+    }
+}
+
+/*
+ *
+ * ===== Retry =====
+ *
+ */
+
+impl<F, R> Future for Retry<F, R>
+    where F: FnMut() -> R,
+          R: IntoFuture,
+{
+    type Item = R::Item;
+    type Error = RetryError<R::Error>;
+
+    fn poll(&mut self) -> Poll<R::Item, RetryError<R::Error>> {
+        loop {
+            let next = match self.state {
+                State::Running(ref mut f) => {
+                    match f.poll() {
+                        Ok(Async::Ready(v)) => return Ok(Async::Ready(v)),
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => State::Waiting(e),
+                    }
+                }
+                State::Waiting(_) => {
+                    match self.backoff.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(Some(_))) => {
+                            State::Running((self.factory)().into_future())
+                        }
+                        Ok(Async::Ready(None)) => {
+                            return Err(RetryError::Exhausted(self.take_error()));
+                        }
+                        Err(e) => {
+                            return Err(RetryError::Timer(self.take_error(), e));
+                        }
+                    }
+                }
+                State::Done => panic!("cannot poll Retry twice"),
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<F, R: IntoFuture> Retry<F, R> {
+    fn take_error(&mut self) -> R::Error {
+        match mem::replace(&mut self.state, State::Done) {
+            State::Waiting(e) => e,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<F, R: IntoFuture> fmt::Debug for Retry<F, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Retry")
+            .field("backoff", &self.backoff)
+            .finish()
+    }
+}
+
+/*
+ *
+ * ===== Errors =====
+ *
+ */
+
+impl<E> RetryError<E> {
+    /// Returns the last error returned by the operation
+    pub fn into_inner(self) -> E {
+        match self {
+            RetryError::Exhausted(e) => e,
+            RetryError::Timer(e, _) => e,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RetryError::Exhausted(ref e) => write!(fmt, "retry budget exhausted: {}", e),
+            RetryError::Timer(ref e, ref err) => write!(fmt, "{}: {}", err, e),
+        }
+    }
+}
+
+impl<E: Error> Error for RetryError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            RetryError::Exhausted(_) => "retry budget exhausted",
+            RetryError::Timer(..) => "timer error while waiting to retry",
+        }
+    }
+}
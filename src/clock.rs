@@ -0,0 +1,83 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// A source of time for a `Timer`
+///
+/// All time related computations performed by a `Timer` go through its
+/// clock: computing the deadline of a `Sleep`, checking whether a `Sleep` has
+/// expired, and deciding how long the timer worker parks before processing
+/// the next timeout.
+///
+/// A custom clock is set with `Builder::clock`. The default clock is
+/// `SystemClock`.
+///
+/// The worker thread parks for the duration between the clock's current
+/// instant and the next timeout, measured in real time. A clock that advances
+/// faster than real time will therefore fire timeouts late.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current instant.
+    ///
+    /// Successive calls must never go backwards.
+    fn now(&self) -> Instant;
+
+    /// Returns the current wall-clock time.
+    ///
+    /// Unlike `now`, the wall-clock time can be stepped forwards or
+    /// backwards. It is used by intervals aligned to wall-clock boundaries.
+    ///
+    /// Defaults to `SystemTime::now()`.
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A `Clock` backed by `Instant::now()` and `SystemTime::now()`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+/// Type erased clock shared by all the parts of a timer.
+#[derive(Clone)]
+pub struct Source(Arc<Clock>);
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<C: Clock> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        (**self).system_time()
+    }
+}
+
+impl Source {
+    pub fn new<C: Clock>(clock: C) -> Source {
+        Source(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    pub fn system_time(&self) -> SystemTime {
+        self.0.system_time()
+    }
+}
+
+impl Default for Source {
+    fn default() -> Source {
+        Source::new(SystemClock)
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Clock")
+    }
+}
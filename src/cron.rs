@@ -0,0 +1,522 @@
+//! A stream firing on the occurrences of a cron expression.
+//!
+//! A `Schedule` is parsed from a cron expression with five fields:
+//!
+//! ```text
+//! minute  hour  day-of-month  month  day-of-week
+//! ```
+//!
+//! or with six fields, the first one being the second:
+//!
+//! ```text
+//! second  minute  hour  day-of-month  month  day-of-week
+//! ```
+//!
+//! Each field is `*`, a value, a range `a-b`, or a comma separated list of
+//! those. Any of them can be followed by a step, as in `*/15` or `8-18/2`.
+//! Months and days of the week can also be given by their three letter
+//! English names, such as `JAN` or `MON`. Sunday is both `0` and `7`, and `?`
+//! is accepted as an alias of `*` in the day fields. When both the day of the
+//! month and the day of the week are restricted, a day matches if either of
+//! them does.
+//!
+//! The shorthands `@yearly`, `@annually`, `@monthly`, `@weekly`, `@daily`,
+//! `@midnight` and `@hourly` are supported as well.
+//!
+//! Occurrences are computed from the wall-clock time of the `Timer`, in UTC.
+//!
+//! ## Example
+//!
+//! ```rust
+//! extern crate futures;
+//! extern crate tokio_timer;
+//!
+//! use futures::Stream;
+//! use tokio_timer::mock;
+//! use tokio_timer::cron::{Cron, Schedule};
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! pub fn main() {
+//!     let timer = mock::Timer::new();
+//!
+//!     // Friday 14 July 2017, 02:40:00 UTC
+//!     let now = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+//!     timer.set_system_time(now);
+//!
+//!     // Every weekday at 03:30
+//!     let schedule: Schedule = "30 3 * * MON-FRI".parse().unwrap();
+//!
+//!     let cron = Cron::new(&timer, schedule);
+//!     let firings = timer.run(cron.take(2).collect()).unwrap();
+//!
+//!     assert_eq!(vec![now + Duration::from_secs(3_000),
+//!                     now + Duration::from_secs(262_200)], firings);
+//! }
+//! ```
+
+use {interval, timer, Sleep, Timer, TimerError};
+
+use futures::{Future, Stream, Async, Poll};
+
+use std::{cmp, fmt};
+use std::error::Error;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The number of days searched for the next occurrence of a schedule. The
+/// Gregorian calendar repeats itself every 400 years.
+const SEARCH_DAYS: i64 = 146_097;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+const MONTHS: &'static [&'static str] = &[
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+const DAYS: &'static [&'static str] = &["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// A stream yielding the wall-clock time of each occurrence of a `Schedule`
+///
+/// The next occurrence is computed from the wall-clock time of the timer, and
+/// the stream sleeps until it is reached. The wall clock is checked at least
+/// once per second while waiting, so that the stream follows the clock when
+/// it is stepped. When several occurrences are missed, because the stream was
+/// polled late or because the clock was stepped forward, only the earliest of
+/// them is yielded.
+///
+/// The stream ends once the schedule has no more occurrences, such as a
+/// schedule for the 30th of February.
+#[derive(Debug)]
+pub struct Cron {
+    schedule: Schedule,
+    sleep: Sleep,
+    // The wall-clock time of the next occurrence
+    next: Option<SystemTime>,
+}
+
+/// A parsed cron expression
+///
+/// See the [module level documentation](index.html) for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // Set when the day field is `*` or `?`
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+/// The error returned when a cron expression cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The expression does not have 5 or 6 fields. Contains the number of
+    /// fields found.
+    FieldCount(usize),
+    /// A field is invalid. Contains the name of the field and its value.
+    InvalidField(&'static str, String),
+}
+
+/// Describes the values accepted by a field of the expression
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+}
+
+const SECOND: Field = Field { name: "second", min: 0, max: 59, names: &[] };
+const MINUTE: Field = Field { name: "minute", min: 0, max: 59, names: &[] };
+const HOUR: Field = Field { name: "hour", min: 0, max: 23, names: &[] };
+const DAY_OF_MONTH: Field = Field { name: "day of month", min: 1, max: 31, names: &[] };
+const MONTH: Field = Field { name: "month", min: 1, max: 12, names: MONTHS };
+// Sunday is both 0 and 7
+const DAY_OF_WEEK: Field = Field { name: "day of week", min: 0, max: 7, names: DAYS };
+
+impl Cron {
+    /// Create a new stream firing on the occurrences of `schedule`, driven by
+    /// `timer`.
+    pub fn new(timer: &Timer, schedule: Schedule) -> Cron {
+        let now = timer.now();
+        let system_time = timer.system_time();
+        let next = schedule.next_after(system_time);
+
+        let mut cron = Cron {
+            schedule: schedule,
+            sleep: timer.sleep_until(now),
+            next: next,
+        };
+
+        cron.reschedule(system_time);
+        cron
+    }
+
+    /// Returns the schedule of this stream.
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Returns the wall-clock time of the next occurrence, or `None` if the
+    /// schedule has no more occurrences.
+    pub fn next_occurrence(&self) -> Option<SystemTime> {
+        self.next
+    }
+
+    /// Sleep until the next occurrence, or until the wall clock has to be
+    /// checked again
+    fn reschedule(&mut self, system_time: SystemTime) {
+        if let Some(next) = self.next {
+            let now = self.sleep.timer().now();
+            let wait = interval::wall_clock_wait(self.sleep.timer(), system_time, next);
+
+            self.sleep.reset(now + wait);
+        }
+    }
+}
+
+impl Stream for Cron {
+    type Item = SystemTime;
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Option<SystemTime>, TimerError> {
+        loop {
+            let next = match self.next {
+                Some(next) => next,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            let _ = try_ready!(self.sleep.poll());
+
+            let system_time = self.sleep.timer().system_time();
+            let tolerance = timer::tolerance(self.sleep.timer());
+
+            if system_time + tolerance >= next {
+                // Occurrences missed while the stream was not polled are
+                // skipped.
+                self.next = self.schedule.next_after(cmp::max(system_time, next));
+                self.reschedule(system_time);
+
+                return Ok(Async::Ready(Some(next)));
+            }
+
+            // The occurrence is not due yet. The sleep only covered part of
+            // the wait, or the wall clock was stepped backwards.
+            self.reschedule(system_time);
+        }
+    }
+}
+
+impl Schedule {
+    /// Parse a cron expression.
+    pub fn parse(expr: &str) -> Result<Schedule, ParseError> {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 0 1 1 *",
+            "@monthly" => "0 0 0 1 * *",
+            "@weekly" => "0 0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 0 * * *",
+            "@hourly" => "0 0 * * * *",
+            expr => expr,
+        };
+
+        let mut fields: Vec<&str> = expr.split_whitespace().collect();
+
+        match fields.len() {
+            5 => fields.insert(0, "0"),
+            6 => {}
+            n => return Err(ParseError::FieldCount(n)),
+        }
+
+        let mut days_of_week = DAY_OF_WEEK.parse(fields[5])?;
+
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(Schedule {
+            seconds: SECOND.parse(fields[0])?,
+            minutes: MINUTE.parse(fields[1])?,
+            hours: HOUR.parse(fields[2])?,
+            days_of_month: DAY_OF_MONTH.parse(fields[3])?,
+            months: MONTH.parse(fields[4])?,
+            days_of_week: days_of_week,
+            any_day_of_month: is_any(fields[3]),
+            any_day_of_week: is_any(fields[5]),
+        })
+    }
+
+    /// Returns the first occurrence of the schedule strictly after `time`, or
+    /// `None` if the schedule has no occurrences.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        // Occurrences fall on whole seconds
+        let secs = unix_secs(time) + 1;
+
+        let mut day = floor_div(secs, SECONDS_PER_DAY);
+        let mut from = (secs - day * SECONDS_PER_DAY) as u32;
+
+        let end = day + SEARCH_DAYS;
+
+        while day <= end {
+            let (year, month, day_of_month) = civil_from_days(day);
+
+            if !has(self.months, month) {
+                // Skip to the first day of the next month
+                day = if month == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, month + 1, 1)
+                };
+                from = 0;
+                continue;
+            }
+
+            if self.matches_day(day_of_month, weekday(day)) {
+                if let Some(secs) = self.next_time_of_day(from) {
+                    return Some(from_unix_secs(day * SECONDS_PER_DAY + secs as i64));
+                }
+            }
+
+            day += 1;
+            from = 0;
+        }
+
+        None
+    }
+
+    /// Returns true if the schedule fires on the given day
+    fn matches_day(&self, day_of_month: u32, day_of_week: u32) -> bool {
+        let dom = has(self.days_of_month, day_of_month);
+        let dow = has(self.days_of_week, day_of_week);
+
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => dow,
+            (false, true) => dom,
+            (false, false) => dom || dow,
+        }
+    }
+
+    /// Returns the first second of the day at or after `from` matching the
+    /// schedule
+    fn next_time_of_day(&self, from: u32) -> Option<u32> {
+        let (hour, minute, second) = (from / 3_600, from / 60 % 60, from % 60);
+
+        for h in hour..24 {
+            if !has(self.hours, h) {
+                continue;
+            }
+
+            let m0 = if h == hour { minute } else { 0 };
+
+            for m in m0..60 {
+                if !has(self.minutes, m) {
+                    continue;
+                }
+
+                let s0 = if h == hour && m == minute { second } else { 0 };
+
+                for s in s0..60 {
+                    if has(self.seconds, s) {
+                        return Some(h * 3_600 + m * 60 + s);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Schedule, ParseError> {
+        Schedule::parse(s)
+    }
+}
+
+impl Field {
+    /// Parse the value of the field into a bit set of the matching values
+    fn parse(&self, value: &str) -> Result<u64, ParseError> {
+        let mut set = 0;
+
+        for part in value.split(',') {
+            set |= self.parse_part(part)
+                .map_err(|_| ParseError::InvalidField(self.name, value.to_owned()))?;
+        }
+
+        Ok(set)
+    }
+
+    /// Parse one element of a list, such as `*/15` or `8-18`
+    fn parse_part(&self, part: &str) -> Result<u64, ()> {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], Some(&part[i + 1..])),
+            None => (part, None),
+        };
+
+        let (start, end) = if range == "*" || range == "?" {
+            (self.min, self.max)
+        } else {
+            match range.find('-') {
+                Some(i) => (self.value(&range[..i])?, self.value(&range[i + 1..])?),
+                // `a/n` is short for `a-max/n`
+                None if step.is_some() => (self.value(range)?, self.max),
+                None => {
+                    let v = self.value(range)?;
+                    (v, v)
+                }
+            }
+        };
+
+        let step = match step {
+            Some(step) => step.parse::<u32>().map_err(|_| ())?,
+            None => 1,
+        };
+
+        if start > end || step == 0 {
+            return Err(());
+        }
+
+        let mut set = 0;
+        let mut v = start;
+
+        while v <= end {
+            set |= 1 << v;
+            v = v.saturating_add(step);
+        }
+
+        Ok(set)
+    }
+
+    /// Parse a single value, either a number or a name
+    fn value(&self, s: &str) -> Result<u32, ()> {
+        let v = match self.names.iter().position(|name| is_name(s, name)) {
+            // Names of months start at 1, names of days at 0
+            Some(i) => i as u32 + self.min,
+            None => s.parse().map_err(|_| ())?,
+        };
+
+        if v < self.min || v > self.max {
+            return Err(());
+        }
+
+        Ok(v)
+    }
+}
+
+/*
+ *
+ * ===== Errors =====
+ *
+ */
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::FieldCount(n) => {
+                write!(fmt, "expected 5 or 6 fields in cron expression, found {}", n)
+            }
+            ParseError::InvalidField(name, ref value) => {
+                write!(fmt, "invalid {} field in cron expression: `{}`", name, value)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseError::FieldCount(_) => "wrong number of fields in cron expression",
+            ParseError::InvalidField(..) => "invalid field in cron expression",
+        }
+    }
+}
+
+/*
+ *
+ * ===== Calendar =====
+ *
+ */
+
+fn is_any(field: &str) -> bool {
+    field == "*" || field == "?"
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Returns true if `s` is `name`, ignoring ASCII case. Names are uppercase.
+fn is_name(s: &str, name: &str) -> bool {
+    s.len() == name.len() && s.bytes().zip(name.bytes()).all(|(a, b)| {
+        let a = if a >= b'a' && a <= b'z' { a - b'a' + b'A' } else { a };
+        a == b
+    })
+}
+
+/// Returns the number of whole seconds since the Unix epoch, rounded down
+fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => {
+            let d = e.duration();
+            let secs = -(d.as_secs() as i64);
+
+            if d.subsec_nanos() > 0 { secs - 1 } else { secs }
+        }
+    }
+}
+
+fn from_unix_secs(secs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(-secs as u64)
+    }
+}
+
+fn floor_div(a: i64, b: i64) -> i64 {
+    let q = a / b;
+
+    if a % b < 0 { q - 1 } else { q }
+}
+
+/// Returns the day of the week of a number of days since the Unix epoch,
+/// Sunday being 0
+fn weekday(days: i64) -> u32 {
+    // The Unix epoch is a Thursday
+    (days + 4 - floor_div(days + 4, 7) * 7) as u32
+}
+
+/// Returns the year, month and day of a number of days since the Unix epoch
+///
+/// See http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = floor_div(z, 146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400;
+
+    (if m <= 2 { y + 1 } else { y }, m as u32, d as u32)
+}
+
+/// Returns the number of days since the Unix epoch of a date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = floor_div(y, 400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let mp = if m > 2 { m - 3 } else { m + 9 };
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,90 @@
+use {timer, Sleep, Timer, TimerError};
+
+use futures::{Future, Stream, Async, Poll};
+
+use std::time::Duration;
+
+/// A stream yielding the most recent item of an underlying stream once that
+/// stream has been quiet for a given duration
+///
+/// Created by `Timer::debounce`. When the underlying stream ends, the pending
+/// item, if any, is yielded right away.
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct Debounce<T: Stream> {
+    stream: T,
+    duration: Duration,
+    sleep: Sleep,
+    // The most recent item, not yielded yet
+    item: Option<T::Item>,
+    // True once the underlying stream has ended
+    done: bool,
+}
+
+/// Create a new `Debounce`
+pub fn new<T: Stream>(timer: &Timer, stream: T, duration: Duration) -> Debounce<T> {
+    Debounce {
+        stream: stream,
+        duration: duration,
+        sleep: timer.sleep_until(timer.now()),
+        item: None,
+        done: false,
+    }
+}
+
+impl<T: Stream> Debounce<T> {
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Consumes the debounce, returning the underlying stream.
+    ///
+    /// The pending item, if any, is lost.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+}
+
+impl<T, E> Stream for Debounce<T>
+    where T: Stream<Error = E>,
+          E: From<TimerError>,
+{
+    type Item = T::Item;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<T::Item>, E> {
+        // Take all the items available from the underlying stream, keeping
+        // the most recent one
+        while !self.done {
+            match self.stream.poll()? {
+                Async::Ready(Some(item)) => {
+                    self.item = Some(item);
+
+                    // Restart the quiet period
+                    let when = timer::deadline(self.sleep.timer().now(), self.duration);
+                    self.sleep.reset(when);
+                }
+                Async::Ready(None) => self.done = true,
+                Async::NotReady => break,
+            }
+        }
+
+        if self.done {
+            return Ok(Async::Ready(self.item.take()));
+        }
+
+        if self.item.is_none() {
+            return Ok(Async::NotReady);
+        }
+
+        try_ready!(self.sleep.poll());
+
+        Ok(Async::Ready(self.item.take()))
+    }
+}
@@ -0,0 +1,200 @@
+use {timer, Sleep, Timer, TimerError};
+use wheel::{Token, Wheel};
+
+use futures::{Future, Stream, Async, Poll};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A queue of values yielded once their deadline is reached
+///
+/// Values are inserted with a deadline, and the queue implements `Stream`,
+/// yielding each value once its deadline is reached. Entries can be removed
+/// or have their deadline changed through the `DelayQueueKey` returned on
+/// insertion.
+///
+/// The stream ends when the queue is empty. It can be polled again once new
+/// values are inserted.
+///
+/// A `DelayQueue` stores its entries in its own timer wheel and only
+/// registers a single timeout with the `Timer`, for the earliest entry. This
+/// makes it suitable for tracking a large number of deadlines, such as one
+/// per connection, where creating a `Sleep` for each one would register a
+/// timeout and send a message to the timer thread per entry:
+///
+/// ```rust
+/// extern crate futures;
+/// extern crate tokio_timer;
+///
+/// use futures::Stream;
+/// use tokio_timer::{mock, DelayQueue};
+/// use std::time::Duration;
+///
+/// pub fn main() {
+///     let timer = mock::Timer::new();
+///     let mut queue = DelayQueue::new(&timer);
+///
+///     queue.insert("b", Duration::from_secs(20));
+///     let key = queue.insert("a", Duration::from_secs(10));
+///     queue.reset(&key, Duration::from_secs(30));
+///
+///     let values = timer.run(queue.collect()).unwrap();
+///     assert_eq!(vec!["b", "a"], values);
+/// }
+/// ```
+pub struct DelayQueue<T> {
+    wheel: Wheel<T>,
+    // Sleeps until the earliest deadline of the wheel
+    sleep: Sleep,
+}
+
+/// Identifies an entry of a `DelayQueue`
+///
+/// A key is only valid until its entry is yielded by the queue or removed.
+/// Keys are reused afterwards, so a stale key may refer to another entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DelayQueueKey {
+    index: usize,
+}
+
+impl<T> DelayQueue<T> {
+    /// Create a new, empty, `DelayQueue` driven by `timer`.
+    pub fn new(timer: &Timer) -> DelayQueue<T> {
+        DelayQueue {
+            wheel: Wheel::embedded(timer),
+            sleep: timer.sleep_until(timer.now()),
+        }
+    }
+
+    /// Insert `value` into the queue, to be yielded after `duration`.
+    pub fn insert(&mut self, value: T, duration: Duration) -> DelayQueueKey {
+        let when = timer::deadline(self.sleep.timer().now(), duration);
+        self.insert_at(value, when)
+    }
+
+    /// Insert `value` into the queue, to be yielded at `when`.
+    pub fn insert_at(&mut self, value: T, when: Instant) -> DelayQueueKey {
+        let token = self.wheel.reserve().expect("delay queue at capacity");
+        self.wheel.set_timeout(token, when, value);
+        self.wake_at(when);
+
+        DelayQueueKey { index: token.0 }
+    }
+
+    /// Remove the entry identified by `key`, returning its value.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `key` does not identify an entry of the
+    /// queue.
+    pub fn remove(&mut self, key: &DelayQueueKey) -> T {
+        self.wheel.remove(Token(key.index)).expect("invalid key")
+    }
+
+    /// Change the deadline of the entry identified by `key` to `duration`
+    /// from now.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `key` does not identify an entry of the
+    /// queue.
+    pub fn reset(&mut self, key: &DelayQueueKey, duration: Duration) {
+        let when = timer::deadline(self.sleep.timer().now(), duration);
+        self.reset_at(key, when);
+    }
+
+    /// Change the deadline of the entry identified by `key` to `when`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `key` does not identify an entry of the
+    /// queue.
+    pub fn reset_at(&mut self, key: &DelayQueueKey, when: Instant) {
+        let token = Token(key.index);
+        let old = self.wheel.deadline(token).expect("invalid key");
+
+        self.wheel.reset(token, old, when);
+        self.wake_at(when);
+    }
+
+    /// Returns the deadline of the entry identified by `key`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `key` does not identify an entry of the
+    /// queue.
+    pub fn deadline(&self, key: &DelayQueueKey) -> Instant {
+        self.wheel.deadline(Token(key.index)).expect("invalid key")
+    }
+
+    /// Returns the instant at which the queue yields its next entry, or
+    /// `None` if it is empty.
+    ///
+    /// The instant is rounded to the precision of the timer.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.wheel.next_timeout()
+    }
+
+    /// Returns the number of entries in the queue.
+    pub fn len(&self) -> usize {
+        self.wheel.len()
+    }
+
+    /// Returns `true` if the queue holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.wheel.is_empty()
+    }
+
+    /// Remove all entries from the queue.
+    pub fn clear(&mut self) {
+        self.wheel.drain();
+    }
+
+    /// Make sure the task polling the queue is notified at `when`
+    fn wake_at(&mut self, when: Instant) {
+        if when < self.sleep.deadline() {
+            self.sleep.reset(when);
+        }
+    }
+}
+
+impl<T> Stream for DelayQueue<T> {
+    type Item = T;
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Option<T>, TimerError> {
+        loop {
+            let now = self.sleep.timer().now();
+
+            if let Some(value) = self.wheel.poll(now) {
+                return Ok(Async::Ready(Some(value)));
+            }
+
+            let when = match self.wheel.next_timeout() {
+                Some(when) => when,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            if self.sleep.deadline() != when {
+                self.sleep.reset(when);
+            }
+
+            try_ready!(self.sleep.poll());
+
+            // Timeouts fire on timer ticks, possibly before `now` reaches
+            // `when`
+            if let Some(value) = self.wheel.poll(when) {
+                return Ok(Async::Ready(Some(value)));
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for DelayQueue<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DelayQueue")
+            .field("len", &self.wheel.len())
+            .field("sleep", &self.sleep)
+            .finish()
+    }
+}
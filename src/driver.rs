@@ -0,0 +1,71 @@
+use {timer, Timer};
+
+use std::time::Instant;
+
+/// Drives a `Timer` that does not spawn a thread
+///
+/// A `Driver` is obtained by calling `Builder::build_threadless`. The timer's
+/// wheel is owned by the `Driver` and timeouts only fire when `turn` is
+/// called, which allows folding the timer into an existing event loop:
+///
+/// ```rust
+/// extern crate tokio_timer;
+///
+/// use std::time::Duration;
+///
+/// pub fn main() {
+///     let (timer, mut driver) = tokio_timer::wheel().build_threadless();
+///
+///     // Tasks running on the event loop use `timer` to set timeouts.
+///     # drop(timer.sleep(Duration::from_secs(1)));
+///
+///     loop {
+///         let now = timer.now();
+///
+///         // Fire the expired timeouts, notifying their tasks.
+///         let next = driver.turn(now);
+///
+///         // Block on the event loop until the next timeout.
+///         let timeout = next.map(|next| {
+///             if next > now { next - now } else { Duration::from_millis(0) }
+///         });
+///
+///         // epoll_wait(epfd, &mut events, timeout);
+///         # drop(timeout);
+///         # break;
+///     }
+/// }
+/// ```
+///
+/// Timeouts set from other threads do not interrupt the event loop. If the
+/// loop is blocked waiting for a later deadline, these timeouts fire late.
+#[derive(Debug)]
+pub struct Driver {
+    timer: Timer,
+}
+
+/// Create a new driver for a timer built with `timer::build_local`
+pub fn new(timer: Timer) -> Driver {
+    Driver { timer: timer }
+}
+
+impl Driver {
+    /// Fire all timeouts that expired at `now`, returning the instant of the
+    /// next timeout.
+    ///
+    /// The tasks of the expired timeouts are notified before this function
+    /// returns. `None` is returned when no timeouts are pending.
+    pub fn turn(&mut self, now: Instant) -> Option<Instant> {
+        timer::turn(&self.timer, now)
+    }
+
+    /// Returns the instant of the next timeout, without firing any.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        timer::local(&self.timer).next_timeout()
+    }
+
+    /// Returns a ref to the timer driven by this `Driver`
+    pub fn timer(&self) -> &Timer {
+        &self.timer
+    }
+}
@@ -0,0 +1,88 @@
+use {schedule, Timer, TimerError};
+
+use futures::{Future, Async, Poll};
+use futures::task::{self, Task};
+
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+/// State whose expired entries are removed by a task driven by the timer
+pub trait Expire: Send + 'static {
+    /// Returns the handle of the task removing the expired entries.
+    fn evictor(&mut self) -> &mut Evictor;
+
+    /// Remove the expired entries, registering the current task to be
+    /// notified once more entries expire.
+    ///
+    /// The returned task is notified once the state is unlocked.
+    fn expire(&mut self) -> Result<Option<Task>, TimerError>;
+}
+
+/// Handle to the task removing the expired entries of an `Expire` value
+///
+/// Dropping the handle lets the task complete.
+#[derive(Debug)]
+pub struct Evictor {
+    task: Option<Task>,
+}
+
+/// Removes the expired entries of the state, until it is dropped
+struct Evict<T> {
+    inner: Weak<Mutex<T>>,
+}
+
+/// Spawn the task removing the expired entries of `inner`.
+pub fn spawn<T: Expire>(timer: &Timer, inner: &Arc<Mutex<T>>) {
+    schedule::spawn(timer, Evict { inner: Arc::downgrade(inner) });
+}
+
+impl Evictor {
+    pub fn new() -> Evictor {
+        Evictor { task: None }
+    }
+
+    /// Returns the task to notify after inserting an entry expiring at `when`
+    /// into a queue whose earliest deadline was `head`.
+    ///
+    /// The task is only returned when it does not already wait for an
+    /// earlier deadline.
+    pub fn wake(&mut self, head: Option<Instant>, when: Instant) -> Option<Task> {
+        match head {
+            Some(head) if head <= when => None,
+            _ => self.task.take(),
+        }
+    }
+}
+
+impl Drop for Evictor {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.notify();
+        }
+    }
+}
+
+impl<T: Expire> Future for Evict<T> {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<(), TimerError> {
+        let inner = match self.inner.upgrade() {
+            Some(inner) => inner,
+            None => return Ok(Async::Ready(())),
+        };
+
+        let task = {
+            let mut inner = inner.lock().unwrap();
+
+            inner.evictor().task = Some(task::current());
+            inner.expire()?
+        };
+
+        if let Some(task) = task {
+            task.notify();
+        }
+
+        Ok(Async::NotReady)
+    }
+}
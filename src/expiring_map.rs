@@ -0,0 +1,334 @@
+use {evictor, DelayQueue, DelayQueueKey, Timer, TimerError};
+use evictor::{Evictor, Expire};
+
+use futures::{Stream, Async, Poll};
+use futures::task::{self, Task};
+
+use std::fmt;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A map whose entries expire after a time-to-live
+///
+/// Expired entries are removed as their deadline is reached by a task spawned
+/// like the futures of `Timer::schedule`. Lookups never return an expired
+/// entry, even if it has not been removed yet.
+///
+/// The `Evictions` stream returned by `evictions` yields the removed entries,
+/// for example to close the sessions they hold. It only observes removals,
+/// entries are freed whether it is polled or not.
+///
+/// With sliding expiration, the deadline of an entry is pushed back every
+/// time it is read, so only entries that are not accessed for their whole
+/// time-to-live expire.
+///
+/// Clones of an `ExpiringMap` refer to the same entries.
+pub struct ExpiringMap<K, V> {
+    inner: Arc<Mutex<Inner<K, V>>>,
+}
+
+/// A stream of the entries removed from an `ExpiringMap` as they expire
+///
+/// Created by `ExpiringMap::evictions`. Entries removed by the timer are
+/// buffered until the stream is polled. The stream ends once every handle to
+/// the map has been dropped and all remaining entries have expired.
+#[must_use = "streams do nothing unless polled"]
+pub struct Evictions<K, V> {
+    inner: Arc<Mutex<Inner<K, V>>>,
+}
+
+struct Inner<K, V> {
+    timer: Timer,
+    entries: HashMap<K, Entry<V>>,
+    queue: DelayQueue<K>,
+    ttl: Duration,
+    sliding: bool,
+    // Number of `ExpiringMap` handles
+    handles: usize,
+    // True once `evictions` has been called
+    evicting: bool,
+    // Entries removed but not yet yielded by `Evictions`, `None` when there
+    // is no `Evictions` stream to yield them
+    evicted: Option<VecDeque<(K, V)>>,
+    // Task polling `Evictions`
+    task: Option<Task>,
+    evictor: Evictor,
+}
+
+struct Entry<V> {
+    value: V,
+    key: DelayQueueKey,
+    ttl: Duration,
+}
+
+impl<K: Hash + Eq + Clone, V> ExpiringMap<K, V> {
+    /// Create a new, empty, `ExpiringMap` whose entries expire `ttl` after
+    /// being inserted.
+    pub fn new(timer: &Timer, ttl: Duration) -> ExpiringMap<K, V>
+        where K: Send + 'static,
+              V: Send + 'static,
+    {
+        let inner = Arc::new(Mutex::new(Inner {
+            timer: timer.clone(),
+            entries: HashMap::new(),
+            queue: DelayQueue::new(timer),
+            ttl: ttl,
+            sliding: false,
+            handles: 1,
+            evicting: false,
+            evicted: None,
+            task: None,
+            evictor: Evictor::new(),
+        }));
+
+        evictor::spawn(timer, &inner);
+
+        ExpiringMap { inner: inner }
+    }
+
+    /// Set whether reading an entry pushes its deadline back by its
+    /// time-to-live.
+    ///
+    /// Defaults to `false`.
+    pub fn sliding_expiration(self, sliding: bool) -> Self {
+        self.inner.lock().unwrap().sliding = sliding;
+        self
+    }
+
+    /// Insert an entry expiring after the default time-to-live of the map,
+    /// returning the previous value for `key`, if any.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let ttl = self.inner.lock().unwrap().ttl;
+        self.insert_with_ttl(key, value, ttl)
+    }
+
+    /// Insert an entry expiring after `ttl`, returning the previous value for
+    /// `key`, if any.
+    ///
+    /// The deadline of an existing entry for `key` is replaced.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let (prev, evictor) = {
+            let mut inner = self.inner.lock().unwrap();
+            let prev = inner.take(&key);
+
+            let head = inner.queue.next_deadline();
+            let queue_key = inner.queue.insert(key.clone(), ttl);
+            let when = inner.queue.deadline(&queue_key);
+
+            inner.entries.insert(key, Entry {
+                value: value,
+                key: queue_key,
+                ttl: ttl,
+            });
+
+            (prev, inner.evictor.wake(head, when))
+        };
+
+        // The evictor may be polled right away, and it locks the map
+        if let Some(task) = evictor {
+            task.notify();
+        }
+
+        prev
+    }
+
+    /// Returns a copy of the value for `key`, if it has not expired.
+    ///
+    /// With sliding expiration, the deadline of the entry is pushed back by
+    /// its time-to-live.
+    pub fn get(&self, key: &K) -> Option<V>
+        where V: Clone,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let now = inner.timer.now();
+
+        let entry = match inner.entries.get(key) {
+            Some(entry) if !entry.is_expired(&inner.queue, now) => entry,
+            _ => return None,
+        };
+
+        if inner.sliding {
+            inner.queue.reset(&entry.key, entry.ttl);
+        }
+
+        Some(entry.value.clone())
+    }
+
+    /// Returns `true` if the map holds an entry for `key` that has not
+    /// expired.
+    ///
+    /// This does not push back the deadline of the entry.
+    pub fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.timer.now();
+
+        match inner.entries.get(key) {
+            Some(entry) => !entry.is_expired(&inner.queue, now),
+            None => false,
+        }
+    }
+
+    /// Remove the entry for `key`, returning its value if it has not
+    /// expired.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.inner.lock().unwrap().take(key)
+    }
+
+    /// Returns the number of entries in the map, including expired entries
+    /// that the timer has not removed yet.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Returns `true` if the map holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the stream of the entries removed from the map as they
+    /// expire.
+    ///
+    /// Only the entries removed after this call are yielded.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it is called more than once for the same map.
+    pub fn evictions(&self) -> Evictions<K, V> {
+        let mut inner = self.inner.lock().unwrap();
+
+        assert!(!inner.evicting, "evictions already taken");
+        inner.evicting = true;
+        inner.evicted = Some(VecDeque::new());
+
+        Evictions { inner: self.inner.clone() }
+    }
+}
+
+impl<K: Hash + Eq, V> Inner<K, V> {
+    /// Remove the entry for `key`, returning its value if it has not expired
+    fn take(&mut self, key: &K) -> Option<V> {
+        let now = self.timer.now();
+
+        let entry = match self.entries.remove(key) {
+            Some(entry) => entry,
+            None => return None,
+        };
+
+        let expired = entry.is_expired(&self.queue, now);
+        self.queue.remove(&entry.key);
+
+        if expired {
+            None
+        } else {
+            Some(entry.value)
+        }
+    }
+}
+
+impl<V> Entry<V> {
+    fn is_expired<K>(&self, queue: &DelayQueue<K>, now: Instant) -> bool {
+        queue.deadline(&self.key) <= now
+    }
+}
+
+impl<K, V> Clone for ExpiringMap<K, V> {
+    fn clone(&self) -> ExpiringMap<K, V> {
+        self.inner.lock().unwrap().handles += 1;
+        ExpiringMap { inner: self.inner.clone() }
+    }
+}
+
+impl<K, V> Drop for ExpiringMap<K, V> {
+    fn drop(&mut self) {
+        let task = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.handles -= 1;
+
+            if inner.handles == 0 {
+                // The evictions stream may now end
+                inner.task.take()
+            } else {
+                None
+            }
+        };
+
+        if let Some(task) = task {
+            task.notify();
+        }
+    }
+}
+
+impl<K, V> Expire for Inner<K, V>
+    where K: Hash + Eq + Send + 'static,
+          V: Send + 'static,
+{
+    fn evictor(&mut self) -> &mut Evictor {
+        &mut self.evictor
+    }
+
+    fn expire(&mut self) -> Result<Option<Task>, TimerError> {
+        let mut task = None;
+
+        // Once the queue is empty, the next insertion wakes the evictor
+        while let Async::Ready(Some(key)) = self.queue.poll()? {
+            let entry = self.entries.remove(&key).expect("entry not found");
+
+            if let Some(ref mut evicted) = self.evicted {
+                evicted.push_back((key, entry.value));
+                task = self.task.take().or(task);
+            }
+        }
+
+        Ok(task)
+    }
+}
+
+impl<K, V> Stream for Evictions<K, V> {
+    type Item = (K, V);
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Option<(K, V)>, TimerError> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+
+        if let Some(entry) = inner.evicted.as_mut().and_then(|evicted| evicted.pop_front()) {
+            return Ok(Async::Ready(Some(entry)));
+        }
+
+        // Without handles, no entry is inserted anymore
+        if inner.handles == 0 && inner.entries.is_empty() {
+            return Ok(Async::Ready(None));
+        }
+
+        inner.task = Some(task::current());
+        Ok(Async::NotReady)
+    }
+}
+
+impl<K, V> Drop for Evictions<K, V> {
+    fn drop(&mut self) {
+        // Stop buffering the removed entries
+        self.inner.lock().unwrap().evicted = None;
+    }
+}
+
+impl<K, V> fmt::Debug for ExpiringMap<K, V> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+
+        fmt.debug_struct("ExpiringMap")
+            .field("len", &inner.entries.len())
+            .field("ttl", &inner.ttl)
+            .field("sliding", &inner.sliding)
+            .finish()
+    }
+}
+
+impl<K, V> fmt::Debug for Evictions<K, V> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Evictions")
+    }
+}
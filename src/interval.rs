@@ -1,23 +1,355 @@
 use futures::{Future, Stream, Async, Poll};
 
-use {Sleep, TimerError};
+use {timer, Jitter, Sleep, Timer, TimerError};
 
-use std::time::Duration;
+use std::cmp;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The longest an aligned interval waits before checking the wall clock again
+const WALL_CLOCK_CHECK_MS: u64 = 1_000;
 
 /// A stream representing notifications at fixed interval
 ///
-/// Intervals are created through `Timer::interval`.
+/// Intervals are created through `Timer::interval`. Ticks are scheduled at
+/// multiples of the period from the instant of the first tick, so an interval
+/// does not drift when the consumer polls it late. What happens when ticks are
+/// missed is defined by its `MissedTickBehavior`.
+///
+/// An interval created by `Timer::interval_aligned` instead follows the wall
+/// clock.
+///
+/// An interval created by `Timer::interval_count` or `Timer::interval_until`
+/// ends, yielding `None`, once its last tick has been yielded.
 #[derive(Debug)]
 pub struct Interval {
+    // Completes at the next tick, or at `until` once no tick remains before it
     sleep: Sleep,
     duration: Duration,
+    // The instant the current period started at. Unknown before the first
+    // tick of an interval created by `Timer::interval_at`.
+    start: Option<Instant>,
+    // The number of ticks left to yield, if limited
+    remaining: Option<u64>,
+    // Ticks are only yielded before this instant, if set
+    until: Option<Instant>,
+    // The wall-clock time of the next tick, if the interval is aligned to the
+    // wall clock
+    wall: Option<SystemTime>,
+    jitter: Option<Jitter>,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+/// A stream yielding a `Tick` describing each tick of an `Interval`
+///
+/// Created by `Interval::ticks`.
+#[derive(Debug)]
+pub struct Ticks {
+    interval: Interval,
 }
 
-/// Create a new interval
-pub fn new(sleep: Sleep, dur: Duration) -> Interval {
+/// A tick of an `Interval`, yielded by `Ticks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tick {
+    scheduled: Instant,
+    actual: Instant,
+    missed: u64,
+}
+
+/// Defines the behavior of an `Interval` when ticks are missed
+///
+/// A tick is missed when the interval is polled later than the instant the
+/// tick was scheduled for, by more than the tick duration of the timer.
+///
+/// The default behavior is `Burst`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Yield the missed ticks as fast as possible until the interval has
+    /// caught up, keeping the schedule of the interval.
+    Burst,
+    /// Schedule the next tick one period after the instant the late tick was
+    /// yielded. The missed ticks are dropped and the schedule shifts.
+    Delay,
+    /// Drop the missed ticks and schedule the next tick at the next multiple
+    /// of the period, keeping the schedule of the interval.
+    Skip,
+}
+
+/// Create a new interval. The first tick fires when `sleep` completes.
+pub fn new(sleep: Sleep, dur: Duration, start: Option<Instant>) -> Interval {
     Interval {
         sleep: sleep,
         duration: dur,
+        start: start,
+        remaining: None,
+        until: None,
+        wall: None,
+        jitter: None,
+        missed_tick_behavior: MissedTickBehavior::default(),
+    }
+}
+
+/// Create a new interval firing at wall-clock multiples of `dur`
+pub fn aligned(timer: Timer, dur: Duration) -> Interval {
+    assert!(nanos(dur) > 0, "interval period must not be zero");
+
+    let now = timer.now();
+    let mut interval = new(timer.sleep_until(now), dur, Some(now));
+
+    interval.realign();
+    interval
+}
+
+/// End `interval` after `count` ticks
+pub fn limit_count(mut interval: Interval, count: u64) -> Interval {
+    interval.remaining = Some(count);
+    interval
+}
+
+/// End `interval` at `deadline`
+pub fn limit_deadline(mut interval: Interval, deadline: Instant) -> Interval {
+    let next = interval.sleep.deadline();
+
+    interval.until = Some(deadline);
+    interval.schedule(next);
+    interval
+}
+
+impl Interval {
+    /// Returns the behavior of the interval when ticks are missed
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    /// Set the behavior of the interval when ticks are missed
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+
+    /// Returns the period of the interval
+    pub fn period(&self) -> Duration {
+        self.duration
+    }
+
+    /// Set the period of the interval.
+    ///
+    /// The pending tick is rescheduled to one new period after the previous
+    /// tick, or after the interval was created if it has not ticked yet. The
+    /// first tick of an interval created by `Timer::interval_at` is not
+    /// rescheduled.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero and the interval is aligned
+    /// to the wall clock.
+    pub fn set_period(&mut self, period: Duration) {
+        if self.wall.is_some() {
+            assert!(nanos(period) > 0, "interval period must not be zero");
+        }
+
+        self.duration = period;
+
+        if self.wall.is_some() {
+            self.realign();
+        } else if let Some(start) = self.start {
+            self.schedule(timer::deadline(start, period));
+        }
+    }
+
+    /// Returns the jitter applied to each period of the interval
+    pub fn jitter(&self) -> Option<Jitter> {
+        self.jitter
+    }
+
+    /// Set the jitter applied to each period of the interval.
+    ///
+    /// Each period is randomized independently, so ticks no longer occur at
+    /// multiples of the period. The pending tick is rescheduled with the
+    /// jitter applied, unless it is the first tick of an interval created by
+    /// `Timer::interval_at`.
+    ///
+    /// Jitter is not applied to intervals aligned to the wall clock.
+    pub fn set_jitter(&mut self, jitter: Option<Jitter>) {
+        self.jitter = jitter;
+
+        if self.wall.is_none() {
+            if let Some(start) = self.start {
+                let period = self.next_period();
+                self.schedule(timer::deadline(start, period));
+            }
+        }
+    }
+
+    /// Restart the interval from the current instant.
+    ///
+    /// The next tick fires one period from now. An interval aligned to the
+    /// wall clock is aligned again instead.
+    pub fn reset(&mut self) {
+        if self.wall.is_some() {
+            self.realign();
+            return;
+        }
+
+        let now = self.sleep.timer().now();
+        let period = self.next_period();
+
+        self.start = Some(now);
+        self.schedule(timer::deadline(now, period));
+    }
+
+    /// Convert the interval into a stream yielding the scheduled and actual
+    /// instants of each tick.
+    pub fn ticks(self) -> Ticks {
+        Ticks { interval: self }
+    }
+
+    fn poll_tick(&mut self) -> Poll<Option<Tick>, TimerError> {
+        if self.remaining == Some(0) {
+            return Ok(Async::Ready(None));
+        }
+
+        loop {
+            let _ = try_ready!(self.sleep.poll());
+
+            let scheduled = self.sleep.deadline();
+
+            if self.until.map(|until| scheduled >= until).unwrap_or(false) {
+                return Ok(Async::Ready(None));
+            }
+
+            let now = self.sleep.timer().now();
+
+            let tick = match self.wall {
+                Some(wall) => {
+                    match self.wall_tick(wall, now) {
+                        Some(tick) => tick,
+                        None => continue,
+                    }
+                }
+                None => self.tick(scheduled, now),
+            };
+
+            if let Some(ref mut remaining) = self.remaining {
+                *remaining -= 1;
+            }
+
+            return Ok(Async::Ready(Some(tick)));
+        }
+    }
+
+    /// Yield the tick scheduled at `scheduled` and schedule the next one
+    fn tick(&mut self, scheduled: Instant, now: Instant) -> Tick {
+        let period = nanos(self.duration);
+        let missed = if now > scheduled && period > 0 {
+            nanos(now - scheduled) / period
+        } else {
+            0
+        };
+
+        let period = self.next_period();
+        let start = self.next_start(scheduled, now, period);
+
+        self.start = Some(start);
+        self.schedule(timer::deadline(start, period));
+
+        Tick {
+            scheduled: scheduled,
+            actual: now,
+            missed: missed,
+        }
+    }
+
+    /// Yield the tick of an aligned interval scheduled at the wall-clock time
+    /// `wall`, if it is due, and schedule the next one.
+    fn wall_tick(&mut self, wall: SystemTime, now: Instant) -> Option<Tick> {
+        let system_time = self.sleep.timer().system_time();
+        let tolerance = timer::tolerance(self.sleep.timer());
+
+        if system_time + tolerance < wall {
+            // The tick is not due yet. The sleep only covered part of the
+            // wait, or the wall clock was stepped backwards, in which case the
+            // interval aligns to the first boundary after the new time.
+            let wall = cmp::min(wall, next_boundary(system_time, self.duration));
+            let wait = wall_clock_wait(self.sleep.timer(), system_time, wall);
+
+            self.wall = Some(wall);
+            self.schedule(timer::deadline(now, wait));
+            return None;
+        }
+
+        let (scheduled, missed) = match system_time.duration_since(wall) {
+            Ok(late) => (now - late, nanos(late) / nanos(self.duration)),
+            Err(e) => (now + e.duration(), 0),
+        };
+
+        // Missed ticks are skipped, the next tick is the first boundary after
+        // this one.
+        let next = next_boundary(cmp::max(system_time, wall), self.duration);
+        let wait = wall_clock_wait(self.sleep.timer(), system_time, next);
+
+        self.wall = Some(next);
+        self.start = Some(now);
+        self.schedule(timer::deadline(now, wait));
+
+        Some(Tick {
+            scheduled: scheduled,
+            actual: now,
+            missed: missed,
+        })
+    }
+
+    /// Align the next tick to the next wall-clock multiple of the period
+    fn realign(&mut self) {
+        let now = self.sleep.timer().now();
+        let system_time = self.sleep.timer().system_time();
+
+        let next = next_boundary(system_time, self.duration);
+        let wait = wall_clock_wait(self.sleep.timer(), system_time, next);
+
+        self.wall = Some(next);
+        self.start = Some(now);
+        self.schedule(timer::deadline(now, wait));
+    }
+
+    /// Reset the sleep to the tick at `next`, or to the end of the interval
+    /// if it is later.
+    fn schedule(&mut self, next: Instant) {
+        match self.until {
+            Some(until) if next >= until => self.sleep.reset(until),
+            _ => self.sleep.reset(next),
+        }
+    }
+
+    /// Returns the duration of the next period, with the jitter applied
+    fn next_period(&self) -> Duration {
+        match self.jitter {
+            Some(ref jitter) => timer::jitter(self.sleep.timer(), self.duration, jitter),
+            None => self.duration,
+        }
+    }
+
+    /// Returns the instant the period following the tick scheduled at
+    /// `scheduled` starts at. The next tick is scheduled one `period` later.
+    fn next_start(&self, scheduled: Instant, now: Instant, period: Duration) -> Instant {
+        let tolerance = timer::tolerance(self.sleep.timer());
+
+        if now <= scheduled + tolerance {
+            return scheduled;
+        }
+
+        match self.missed_tick_behavior {
+            MissedTickBehavior::Burst => scheduled,
+            MissedTickBehavior::Delay => now,
+            MissedTickBehavior::Skip => {
+                let period = nanos(period);
+
+                if period == 0 {
+                    return now;
+                }
+
+                let late = nanos(now - scheduled) % period;
+                now - from_nanos(late)
+            }
+        }
     }
 }
 
@@ -26,11 +358,106 @@ impl Stream for Interval {
     type Error = TimerError;
 
     fn poll(&mut self) -> Poll<Option<()>, TimerError> {
-        let _ = try_ready!(self.sleep.poll());
+        let tick = try_ready!(self.poll_tick());
+        Ok(Async::Ready(tick.map(|_| ())))
+    }
+}
 
-        // Reset the timeout
-        self.sleep = self.sleep.timer().sleep(self.duration);
+impl Ticks {
+    /// Returns a reference to the underlying interval
+    pub fn get_ref(&self) -> &Interval {
+        &self.interval
+    }
 
-        Ok(Async::Ready(Some(())))
+    /// Returns a mutable reference to the underlying interval
+    pub fn get_mut(&mut self) -> &mut Interval {
+        &mut self.interval
     }
+
+    /// Consumes the stream, returning the underlying interval
+    pub fn into_inner(self) -> Interval {
+        self.interval
+    }
+}
+
+impl Stream for Ticks {
+    type Item = Tick;
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Option<Tick>, TimerError> {
+        self.interval.poll_tick()
+    }
+}
+
+impl Tick {
+    /// Returns the instant the tick was scheduled for
+    pub fn scheduled(&self) -> Instant {
+        self.scheduled
+    }
+
+    /// Returns the instant the tick was yielded
+    ///
+    /// This can be slightly before the scheduled instant, as the timer is not
+    /// precise.
+    pub fn actual(&self) -> Instant {
+        self.actual
+    }
+
+    /// Returns how late the tick was yielded
+    pub fn lateness(&self) -> Duration {
+        if self.actual > self.scheduled {
+            self.actual - self.scheduled
+        } else {
+            Duration::from_millis(0)
+        }
+    }
+
+    /// Returns the number of whole periods that elapsed between the scheduled
+    /// and actual instants of the tick.
+    ///
+    /// With `MissedTickBehavior::Burst`, the ticks of these periods are still
+    /// yielded. With `Delay` and `Skip`, they are dropped.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+impl Default for MissedTickBehavior {
+    fn default() -> MissedTickBehavior {
+        MissedTickBehavior::Burst
+    }
+}
+
+/// Returns how long to sleep before checking the wall clock again when
+/// waiting for `wall`
+pub fn wall_clock_wait(timer: &Timer, system_time: SystemTime, wall: SystemTime) -> Duration {
+    // Sleep longer than the timer's precision, so that the sleep does not
+    // complete right away.
+    let tolerance = timer::tolerance(timer);
+    let check = cmp::max(Duration::from_millis(WALL_CLOCK_CHECK_MS), tolerance * 2);
+
+    let remaining = wall.duration_since(system_time)
+        .unwrap_or(Duration::from_millis(0));
+
+    cmp::min(remaining, check)
+}
+
+/// Returns the first wall-clock multiple of `period` after `system_time`
+fn next_boundary(system_time: SystemTime, period: Duration) -> SystemTime {
+    let since_epoch = system_time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_millis(0));
+
+    let offset = nanos(since_epoch) % nanos(period);
+
+    timer::deadline(system_time, period - from_nanos(offset))
+}
+
+pub fn nanos(duration: Duration) -> u64 {
+    duration.as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(duration.subsec_nanos() as u64)
+}
+
+pub fn from_nanos(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
 }
@@ -0,0 +1,100 @@
+use interval::{from_nanos, nanos};
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Randomizes a duration within bounds
+///
+/// Jitter is used to spread out the timeouts of many clients that would
+/// otherwise fire at the same time. The random numbers are taken from the
+/// generator of the timer, see `Builder::jitter_seed`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jitter {
+    /// Add a random duration between minus and plus the given duration.
+    Uniform(Duration),
+    /// Add a random duration between minus and plus the given fraction of
+    /// the duration being randomized. The fraction is expected to be between
+    /// 0 and 1, see `Jitter::proportional`.
+    Proportional(f64),
+}
+
+/// Xorshift pseudo random number generator. Not suitable for cryptographic
+/// purposes.
+#[derive(Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Jitter {
+    /// Create a jitter of up to `fraction` of the duration being randomized.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `fraction` is negative or NaN.
+    pub fn proportional(fraction: f64) -> Jitter {
+        assert!(fraction >= 0.0, "jitter fraction must be a non-negative number");
+        Jitter::Proportional(fraction)
+    }
+
+    /// Returns `duration` with a random jitter applied. The result is never
+    /// negative.
+    pub fn apply(&self, duration: Duration, rng: &mut Rng) -> Duration {
+        let base = nanos(duration) as f64;
+
+        let spread = match *self {
+            Jitter::Uniform(max) => nanos(max) as f64,
+            Jitter::Proportional(fraction) => base * fraction.abs(),
+        };
+
+        let offset = (rng.next_f64() * 2.0 - 1.0) * spread;
+        let jittered = base + offset;
+
+        if jittered.is_nan() {
+            // Built from a NaN fraction without `Jitter::proportional`
+            duration
+        } else if jittered <= 0.0 {
+            Duration::from_millis(0)
+        } else if jittered >= u64::max_value() as f64 {
+            from_nanos(u64::max_value())
+        } else {
+            from_nanos(jittered as u64)
+        }
+    }
+}
+
+impl Rng {
+    /// Create a generator producing the sequence identified by `seed`
+    pub fn new(seed: u64) -> Rng {
+        // Mix the seed with splitmix64, so that close seeds produce unrelated
+        // sequences and the state is never zero.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        Rng { state: if z == 0 { 1 } else { z } }
+    }
+
+    /// Create a generator with a random seed
+    pub fn from_entropy() -> Rng {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        Rng::new(hasher.finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        // xorshift64*
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
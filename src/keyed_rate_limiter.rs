@@ -0,0 +1,243 @@
+use {evictor, timer, DelayQueue, DelayQueueKey, Sleep, Timer, TimerError};
+use evictor::{Evictor, Expire};
+use interval::{from_nanos, nanos};
+
+use futures::{Future, Stream, Async, Poll};
+use futures::task::Task;
+
+use std::{cmp, fmt};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A rate limiter tracking a separate limit for each key
+///
+/// Each key, for example a client address, may be allowed `burst` times at
+/// once and is then limited to a constant rate. The limit is implemented with
+/// the generic cell rate algorithm (GCRA), which only stores a single instant
+/// per key: the theoretical arrival time of the next request.
+///
+/// A key whose theoretical arrival time has passed is in the same state as a
+/// key that was never seen, so it is dropped. Keys are queued in a
+/// `DelayQueue` until their theoretical arrival time and dropped by a task
+/// spawned like the futures of `Timer::schedule`. Memory stays proportional
+/// to the number of recently active keys, even when the limiter is idle.
+///
+/// Clones of a `KeyedRateLimiter` share the limits of every key.
+pub struct KeyedRateLimiter<K> {
+    timer: Timer,
+    // Interval between two requests allowed at the sustained rate
+    emission: Duration,
+    // How far ahead of the current instant the theoretical arrival time of a
+    // key may be
+    limit: Duration,
+    state: Arc<Mutex<State<K>>>,
+}
+
+/// A future completing once a key is allowed by a `KeyedRateLimiter`
+///
+/// Created by `KeyedRateLimiter::acquire`. Unlike `RateLimiter`, nothing is
+/// reserved while waiting: the key is checked again once the earliest instant
+/// it could be allowed at is reached.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct KeyedAcquire<K> {
+    limiter: KeyedRateLimiter<K>,
+    key: K,
+    sleep: Sleep,
+    waiting: bool,
+}
+
+struct State<K> {
+    keys: HashMap<K, Entry>,
+    // Each tracked key, until its theoretical arrival time
+    queue: DelayQueue<K>,
+    evictor: Evictor,
+}
+
+struct Entry {
+    // Theoretical arrival time of the next request
+    tat: Instant,
+    key: DelayQueueKey,
+}
+
+impl<K: Hash + Eq + Clone> KeyedRateLimiter<K> {
+    /// Create a rate limiter allowing `tokens` requests every `per` for each
+    /// key, with bursts of up to `burst` requests.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `tokens`, `per` or `burst` is zero.
+    pub fn new(timer: &Timer, tokens: u64, per: Duration, burst: u64) -> KeyedRateLimiter<K>
+        where K: Send + 'static,
+    {
+        assert!(tokens > 0 && nanos(per) > 0, "refill rate must not be zero");
+        assert!(burst > 0, "burst must allow at least one request");
+
+        let emission = from_nanos(cmp::max(nanos(per) / tokens, 1));
+
+        let state = Arc::new(Mutex::new(State {
+            keys: HashMap::new(),
+            queue: DelayQueue::new(timer),
+            evictor: Evictor::new(),
+        }));
+
+        evictor::spawn(timer, &state);
+
+        KeyedRateLimiter {
+            timer: timer.clone(),
+            emission: emission,
+            limit: from_nanos(nanos(emission).saturating_mul(burst)),
+            state: state,
+        }
+    }
+
+    /// Check whether a request for `key` is allowed right now, recording it
+    /// if it is.
+    ///
+    /// When the request is not allowed, the earliest instant at which it
+    /// could be is returned.
+    pub fn check(&self, key: &K) -> Result<(), Instant> {
+        let now = self.timer.now();
+        self.check_at(key, now)
+    }
+
+    /// Returns a future completing once a request for `key` is allowed.
+    pub fn acquire(&self, key: K) -> KeyedAcquire<K> {
+        let now = self.timer.now();
+
+        KeyedAcquire {
+            limiter: self.clone(),
+            key: key,
+            sleep: self.timer.sleep_until(now),
+            waiting: false,
+        }
+    }
+
+    /// Returns the number of keys currently tracked, including keys whose
+    /// theoretical arrival time has passed but that the timer has not
+    /// dropped yet.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().keys.len()
+    }
+
+    /// Returns `true` if no key is currently tracked.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn check_at(&self, key: &K, now: Instant) -> Result<(), Instant> {
+        let evictor = {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+
+            // A key that has not been dropped yet may already be expired
+            let tat = match state.keys.get(key) {
+                Some(entry) if entry.tat > now => entry.tat,
+                _ => now,
+            };
+
+            let new = timer::deadline(tat, self.emission);
+
+            // Compare durations, as `now + limit` may not be representable
+            let ahead = new - now;
+
+            if ahead > self.limit {
+                return Err(now + (ahead - self.limit));
+            }
+
+            let head = state.queue.next_deadline();
+
+            let tracked = state.keys.get_mut(key).map(|entry| {
+                entry.tat = new;
+                entry.key
+            });
+
+            match tracked {
+                Some(queue_key) => state.queue.reset_at(&queue_key, new),
+                None => {
+                    let queue_key = state.queue.insert_at(key.clone(), new);
+                    state.keys.insert(key.clone(), Entry { tat: new, key: queue_key });
+                }
+            }
+
+            state.evictor.wake(head, new)
+        };
+
+        // Waking the evictor may poll it before returning, and it locks the
+        // state of the limiter
+        if let Some(task) = evictor {
+            task.notify();
+        }
+
+        Ok(())
+    }
+}
+
+impl<K> Expire for State<K>
+    where K: Hash + Eq + Send + 'static,
+{
+    fn evictor(&mut self) -> &mut Evictor {
+        &mut self.evictor
+    }
+
+    fn expire(&mut self) -> Result<Option<Task>, TimerError> {
+        // The deadline of a key follows its theoretical arrival time, so the
+        // queue only yields keys that are no longer limited
+        while let Async::Ready(Some(key)) = self.queue.poll()? {
+            self.keys.remove(&key);
+        }
+
+        Ok(None)
+    }
+}
+
+impl<K> Clone for KeyedRateLimiter<K> {
+    fn clone(&self) -> KeyedRateLimiter<K> {
+        KeyedRateLimiter {
+            timer: self.timer.clone(),
+            emission: self.emission,
+            limit: self.limit,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for KeyedRateLimiter<K> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("KeyedRateLimiter")
+            .field("emission", &self.emission)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone> Future for KeyedAcquire<K> {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<(), TimerError> {
+        loop {
+            let mut now = self.limiter.timer.now();
+
+            if self.waiting {
+                if self.sleep.poll()?.is_not_ready() {
+                    return Ok(Async::NotReady);
+                }
+
+                // Check as of the instant the key was reported allowed at,
+                // which the timer may not quite have reached
+                now = cmp::max(now, self.sleep.deadline());
+            }
+
+            match self.limiter.check_at(&self.key, now) {
+                Ok(()) => return Ok(Async::Ready(())),
+                Err(when) => {
+                    self.sleep.reset(when);
+                    self.waiting = true;
+                }
+            }
+        }
+    }
+}
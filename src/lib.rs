@@ -1,10 +1,9 @@
 //! Timer facilities for Tokio
 //!
-//! The default timer implementation is a hashed timing wheel. This structure
-//! provides the best runtime characteristics for the majority of network
-//! application patterns **as long as it is correctly configured**. A hashed
-//! timing wheel's worst case is `O(n)` where `n` is the number of pending
-//! timeouts.
+//! The default timer implementation is a hierarchical hashed timing wheel.
+//! This structure provides constant time insertion and cancellation of
+//! timeouts regardless of how far into the future they are set, which makes it
+//! a good fit for the majority of network application patterns.
 //!
 //! Most useful functions are on [`Timer`](struct.Timer.html).
 //!
@@ -36,14 +35,12 @@
 //! }
 //! ```
 //!
-//! ## Hashed Timing Wheel
+//! ## Hierarchical Hashed Timing Wheel
 //!
 //! The hashed timing wheel timer is a coarse grained timer that is optimized
-//! for cases where the timeout range is relatively uniform and high precision
-//! is not needed. These requirements are very common with network related
-//! applications as most timeouts tend to be a constant range (for example, 30
-//! seconds) and timeouts are used more as a safe guard than for high
-//! precision.
+//! for cases where high precision is not needed. This requirement is very
+//! common with network related applications as timeouts are used more as a
+//! safe guard than for high precision.
 //!
 //! The timer is inspired by the [paper by Varghese and
 //! Lauck](http://www.cs.columbia.edu/~nahum/w6998/papers/ton97-timing-wheels.pdf).
@@ -59,17 +56,17 @@
 //! request that falls within that 100ms slot will be triggered at the same
 //! time.
 //!
-//! A timer is assigned to a slot by taking the expiration instant and
-//! assigning it to a slot, factoring in wrapping. When there are more than one
-//! timeouts assigned to a given slot, they are stored in a linked list.
+//! In order to support timeouts of any length without collisions, the timer
+//! is made up of several wheels, or levels. Each slot of the first level
+//! represents a single tick, each slot of the second level represents a full
+//! rotation of the first level, and so on. A timeout is stored in the finest
+//! level able to represent it. When the timer reaches a slot of a coarser
+//! level, the timeouts stored in it are cascaded down into the finer levels,
+//! until they reach the first level and fire. Both setting and cancelling a
+//! timeout are `O(1)` operations.
 //!
-//! This structure allows constant time timer operations **as long as timeouts
-//! don't collide**. In other words, if two timeouts are set to expire at
-//! exactly `num-slots * tick-duration` time apart, they will be assigned to
-//! the same bucket.
-//!
-//! The best way to avoid collisions is to ensure that no timeout is set that
-//! is for greater than `num-slots * tick-duration` into the future.
+//! With the default settings of 4,096 slots per level and a 100ms tick, the
+//! first level covers about 6 minutes and the second level about 19 days.
 //!
 //! A timer can be configured with `Builder`.
 //!
@@ -79,6 +76,12 @@
 //! on this thread. When `Timer::set_timeout` is called, a request is sent to
 //! the thread over a bounded channel.
 //!
+//! Alternatively, `Builder::build_threadless` creates a timer that does not
+//! spawn a thread. Timeouts are then set directly in the wheel, and the wheel
+//! is processed by calling `Driver::turn` from an existing event loop. With
+//! the `tokio-core` feature enabled, `Builder::build_with_handle` drives the
+//! timer from a `tokio-core` reactor in the same way.
+//!
 //! All storage needed to run the timer is pre-allocated, which means that the
 //! timer system is able to run without any runtime allocations. The one
 //! exception would be if the timer's `max_capacity` is larger than the
@@ -86,19 +89,60 @@
 //! needed. Timeout storage can grow but never shrink.
 
 #![deny(warnings, missing_docs, missing_debug_implementations)]
+// Trait objects are written without `dyn`, which Rust 1.14 does not support
+#![allow(unknown_lints, bare_trait_objects)]
 
 #[macro_use]
 extern crate futures;
 extern crate slab;
 
+#[cfg(feature = "tokio-core")]
+extern crate tokio_core;
+
+mod backoff;
+mod clock;
+pub mod cron;
+mod debounce;
+mod delay_queue;
+mod driver;
+mod evictor;
+mod expiring_map;
 mod interval;
+mod jitter;
+mod keyed_rate_limiter;
+pub mod mock;
 mod mpmc;
+mod rate_limiter;
+#[cfg(feature = "tokio-core")]
+mod reactor;
+mod schedule;
+mod throttle;
 mod timer;
+mod watchdog;
 mod wheel;
 mod worker;
 
-pub use interval::Interval;
-pub use timer::{Sleep, Timer, Timeout, TimeoutStream, TimerError, TimeoutError};
+pub use backoff::{Backoff, BackoffPolicy, Retry, RetryError};
+pub use clock::{Clock, SystemClock};
+pub use debounce::Debounce;
+pub use delay_queue::{DelayQueue, DelayQueueKey};
+pub use driver::Driver;
+pub use expiring_map::{Evictions, ExpiringMap};
+pub use interval::{Interval, MissedTickBehavior, Tick, Ticks};
+pub use jitter::Jitter;
+pub use keyed_rate_limiter::{KeyedAcquire, KeyedRateLimiter};
+pub use rate_limiter::{Acquire, RateLimiter};
+pub use schedule::Scheduled;
+pub use throttle::RateLimit;
+pub use timer::{Sleep, Shutdown, Timer, Timeout, TimeoutStream, TimerError, TimeoutError};
+pub use watchdog::{Watchdog, WatchdogHandle};
+
+use clock::Source;
+use jitter::Rng;
+use schedule::Spawner;
+
+use futures::Future;
+use futures::future::Executor;
 
 use std::cmp;
 use std::time::Duration;
@@ -115,6 +159,10 @@ pub struct Builder {
     max_timeout: Option<Duration>,
     channel_capacity: Option<usize>,
     thread_name: Option<String>,
+    clock: Option<Source>,
+    respawn_on_panic: bool,
+    jitter_seed: Option<u64>,
+    executor: Option<Spawner>,
 }
 
 /// Configure and build a `Timer` backed by a hashed wheel.
@@ -127,6 +175,10 @@ pub fn wheel() -> Builder {
         max_timeout: None,
         channel_capacity: None,
         thread_name: None,
+        clock: None,
+        respawn_on_panic: false,
+        jitter_seed: None,
+        executor: None,
     }
 }
 
@@ -150,9 +202,11 @@ impl Builder {
         self.num_slots.unwrap_or(4_096)
     }
 
-    /// Set the number of slots in the timer wheel.
+    /// Set the number of slots in each level of the timer wheel.
     ///
-    /// The number of slots must be a power of two.
+    /// The number of slots must be a power of two greater than one. Fewer
+    /// slots result in more levels and more frequent cascading of timeouts
+    /// between levels.
     ///
     /// See the crate docs for more detail.
     ///
@@ -194,19 +248,16 @@ impl Builder {
         self
     }
 
-    fn get_max_timeout(&self) -> Duration {
-        let default = self.get_tick_duration() * self.get_num_slots() as u32;
-        self.max_timeout.unwrap_or(default)
+    fn get_max_timeout(&self) -> Option<Duration> {
+        self.max_timeout
     }
 
     /// Set the max timeout duration that can be requested
     ///
-    /// Setting the max timeout allows preventing the case of timeout collision
-    /// in the hash wheel and helps guarantee optimial runtime characteristics.
+    /// Requesting a timeout longer than this duration results in
+    /// `TimerError::TooLong`.
     ///
-    /// See the crate docs for more detail.
-    ///
-    /// Defaults to `num_slots * tick_duration`
+    /// Defaults to no limit.
     pub fn max_timeout(mut self, max_timeout: Duration) -> Self {
         self.max_timeout = Some(max_timeout);
         self
@@ -242,8 +293,100 @@ impl Builder {
         self
     }
 
+    fn get_respawn_on_panic(&self) -> bool {
+        self.respawn_on_panic
+    }
+
+    /// Set whether the timer keeps processing timeouts after a panic.
+    ///
+    /// The timer thread catches panics raised while processing timeouts, and
+    /// all of its pending timeouts are then lost. If `respawn` is `true`, the
+    /// same thread resumes processing timeouts with an empty wheel, and the
+    /// affected `Sleep` instances are notified and set their timeout again
+    /// when polled. Otherwise, the timer is poisoned and polling any `Sleep`
+    /// backed by it returns `TimerError::Poisoned`.
+    ///
+    /// Defaults to `false`.
+    pub fn respawn_on_panic(mut self, respawn: bool) -> Self {
+        self.respawn_on_panic = respawn;
+        self
+    }
+
+    fn get_clock(&self) -> Source {
+        self.clock.clone().unwrap_or_else(Source::default)
+    }
+
+    /// Set the source of time used by the timer.
+    ///
+    /// Every deadline computation and expiration check performed by the timer,
+    /// as well as the parking of the timer thread, reads the current time from
+    /// this clock.
+    ///
+    /// Defaults to `SystemClock`.
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = Some(Source::new(clock));
+        self
+    }
+
+    fn get_rng(&self) -> Rng {
+        match self.jitter_seed {
+            Some(seed) => Rng::new(seed),
+            None => Rng::from_entropy(),
+        }
+    }
+
+    /// Set the seed of the random number generator used to apply `Jitter`.
+    ///
+    /// Timers built with the same seed apply the same sequence of jitter.
+    ///
+    /// Defaults to a random seed, except for `mock::Timer`.
+    pub fn jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter_seed = Some(seed);
+        self
+    }
+
+    fn get_executor(&self) -> Option<Spawner> {
+        self.executor.clone()
+    }
+
+    /// Set the executor running the closures scheduled with
+    /// `Timer::schedule` and its periodic variants.
+    ///
+    /// Defaults to running them on the timer thread, or on the thread turning
+    /// the `Driver` of a threadless timer.
+    pub fn executor<E>(mut self, executor: E) -> Self
+        where E: Executor<Box<Future<Item = (), Error = ()> + Send>> + Send + Sync + 'static,
+    {
+        self.executor = Some(Spawner::new(executor));
+        self
+    }
+
     /// Build the configured `Timer` and return a handle to it.
     pub fn build(self) -> Timer {
         timer::build(self)
     }
+
+    /// Build the configured `Timer` without spawning a thread.
+    ///
+    /// Timeouts only fire when the returned `Driver` is turned. The
+    /// `thread_name` and `channel_capacity` settings are ignored.
+    pub fn build_threadless(self) -> (Timer, Driver) {
+        let timer = timer::build_local(self);
+        (timer.clone(), driver::new(timer))
+    }
+
+    /// Build the configured `Timer`, driven by the reactor of `handle`.
+    ///
+    /// No thread is spawned. Instead, a background future processing the
+    /// timer is spawned on the reactor and timeouts fire as part of the
+    /// reactor's turn. The background future completes once all the handles
+    /// to the timer have been dropped.
+    ///
+    /// The `thread_name` and `channel_capacity` settings are ignored.
+    ///
+    /// This function requires the `tokio-core` feature.
+    #[cfg(feature = "tokio-core")]
+    pub fn build_with_handle(self, handle: &tokio_core::reactor::Handle) -> Timer {
+        reactor::build(self, handle)
+    }
 }
@@ -0,0 +1,231 @@
+//! A mock timer for testing code that depends on time.
+//!
+//! The time of a mock timer only moves forward when `Timer::advance` is
+//! called. Advancing the time synchronously notifies the tasks of every
+//! `Sleep`, `Timeout`, `TimeoutStream` and `Interval` whose deadline has been
+//! reached, which allows testing timing dependent code without actually
+//! waiting.
+//!
+//! ## Example
+//!
+//! ```rust
+//! extern crate tokio_timer;
+//!
+//! use tokio_timer::mock;
+//! use std::time::Duration;
+//!
+//! pub fn main() {
+//!     let timer = mock::Timer::new();
+//!     let sleep = timer.sleep(Duration::from_secs(60));
+//!
+//!     assert!(!sleep.is_expired());
+//!
+//!     timer.advance(Duration::from_secs(60));
+//!
+//!     assert!(sleep.is_expired());
+//! }
+//! ```
+//!
+//! ## Virtual time
+//!
+//! `Timer::run` runs a future to completion on the current thread. Whenever
+//! the future is idle, the time of the timer jumps straight to the next
+//! timeout. Code waiting on long sleeps or intervals completes in a fraction
+//! of the real time, with exact and reproducible timing.
+//!
+//! ```rust
+//! extern crate futures;
+//! extern crate tokio_timer;
+//!
+//! use futures::Stream;
+//! use tokio_timer::mock;
+//! use std::time::Duration;
+//!
+//! pub fn main() {
+//!     let timer = mock::Timer::new();
+//!     let start = timer.now();
+//!
+//!     let interval = timer.interval(Duration::from_secs(60));
+//!     timer.run(interval.take(10).collect()).unwrap();
+//!
+//!     assert_eq!(Duration::from_secs(600), timer.now() - start);
+//! }
+//! ```
+
+use {timer, wheel, Builder, Clock};
+
+use futures::{Future, Async};
+use futures::executor::{self, Notify, NotifyHandle};
+
+use std::cmp;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A `Timer` whose time only moves forward when `advance` is called
+///
+/// `Timer` dereferences to the `tokio_timer::Timer` handle used to create
+/// sleeps, timeouts and intervals. The handle can be cloned and passed to the
+/// code under test.
+#[derive(Debug, Clone)]
+pub struct Timer {
+    timer: ::Timer,
+    clock: Arc<MockClock>,
+}
+
+#[derive(Debug)]
+struct MockClock {
+    now: Mutex<Now>,
+}
+
+#[derive(Debug)]
+struct Now {
+    instant: Instant,
+    system_time: SystemTime,
+}
+
+/// Notifies the thread running `Timer::run`
+struct ThreadNotify {
+    notified: AtomicBool,
+    thread: Thread,
+}
+
+impl Timer {
+    /// Create a new mock timer with a tick duration of 1ms.
+    pub fn new() -> Timer {
+        Timer::from_builder(wheel().tick_duration(Duration::from_millis(1)))
+    }
+
+    /// Create a new mock timer using the settings of `builder`.
+    ///
+    /// The clock configured on `builder` is replaced by the mock clock, which
+    /// starts at the current instant. No thread is spawned.
+    ///
+    /// Unless `Builder::jitter_seed` is set, the random number generator of
+    /// the timer is seeded with a fixed value, so that jitter is the same on
+    /// every run.
+    pub fn from_builder(mut builder: Builder) -> Timer {
+        if builder.jitter_seed.is_none() {
+            builder.jitter_seed = Some(0);
+        }
+
+        let clock = Arc::new(MockClock {
+            now: Mutex::new(Now {
+                instant: Instant::now(),
+                system_time: SystemTime::now(),
+            }),
+        });
+
+        Timer {
+            timer: timer::build_local(builder.clock(clock.clone())),
+            clock: clock,
+        }
+    }
+
+    /// Move the time of the timer forward by `duration`.
+    ///
+    /// The tasks of all timeouts that expire during this period are notified
+    /// before this function returns.
+    pub fn advance(&self, duration: Duration) {
+        let now = {
+            let mut now = self.clock.now.lock().unwrap();
+            now.instant += duration;
+            now.system_time += duration;
+            now.instant
+        };
+
+        timer::turn(&self.timer, now);
+    }
+
+    /// Step the wall-clock time of the timer to `time`.
+    ///
+    /// Only `Clock::system_time` is affected, the instant returned by `now`
+    /// does not change. The wall-clock time keeps moving forward along with
+    /// the instant when the timer is advanced.
+    pub fn set_system_time(&self, time: SystemTime) {
+        self.clock.now.lock().unwrap().system_time = time;
+    }
+
+    /// Run `future` to completion on the current thread, advancing time
+    /// whenever the future is idle.
+    ///
+    /// Each time the future returns `NotReady` and has not been notified, the
+    /// time of the timer jumps directly to the next timeout and the expired
+    /// timeouts are fired. If no timeouts remain, the thread blocks until the
+    /// future is notified by another thread.
+    ///
+    /// The future is expected to be driven by the timer. A future waiting on
+    /// an event from another thread sees time jump ahead while it waits.
+    pub fn run<F: Future>(&self, future: F) -> Result<F::Item, F::Error> {
+        let notify = Arc::new(ThreadNotify {
+            notified: AtomicBool::new(false),
+            thread: thread::current(),
+        });
+
+        let handle = NotifyHandle::from(notify.clone());
+        let mut future = executor::spawn(future);
+
+        loop {
+            notify.notified.store(false, Ordering::SeqCst);
+
+            if let Async::Ready(v) = future.poll_future_notify(&handle, 0)? {
+                return Ok(v);
+            }
+
+            // Fire timeouts until the future is notified
+            while !notify.notified.load(Ordering::SeqCst) {
+                let now = self.clock.now();
+
+                match timer::turn(&self.timer, now) {
+                    _ if notify.notified.load(Ordering::SeqCst) => {}
+                    Some(next) => self.set_now(cmp::max(now, next)),
+                    None => thread::park(),
+                }
+            }
+        }
+    }
+
+    fn set_now(&self, instant: Instant) {
+        let mut now = self.clock.now.lock().unwrap();
+
+        if instant > now.instant {
+            let elapsed = instant - now.instant;
+
+            now.instant = instant;
+            now.system_time += elapsed;
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
+
+impl Deref for Timer {
+    type Target = ::Timer;
+
+    fn deref(&self) -> &::Timer {
+        &self.timer
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().instant
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().system_time
+    }
+}
+
+impl Notify for ThreadNotify {
+    fn notify(&self, _: usize) {
+        self.notified.store(true, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
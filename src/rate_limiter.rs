@@ -0,0 +1,319 @@
+use {timer, Sleep, Timer, TimerError};
+use interval::{from_nanos, nanos};
+
+use futures::{Future, Async, Poll};
+use futures::task::{self, Task};
+use slab::Slab;
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A token bucket rate limiter
+///
+/// The bucket holds up to `burst` tokens and is refilled at a constant rate.
+/// `acquire` returns a future completing once the requested tokens are
+/// available. The timer notifies waiting tasks when enough tokens have been
+/// refilled.
+///
+/// Tokens are reserved when `acquire` is called, in the order of the calls, so
+/// waiting acquisitions complete in FIFO order and a large acquisition is not
+/// starved by smaller ones. Dropping an `Acquire` before it completes returns
+/// its tokens to the bucket.
+///
+/// `RateLimiter` handles are cheap to clone and share the same bucket.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+/// A future completing once the tokens requested from a `RateLimiter` are
+/// available
+///
+/// Created by `RateLimiter::acquire`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Acquire {
+    limiter: RateLimiter,
+    // The key of the waiter in the limiter's slab
+    key: usize,
+    tokens: u64,
+    sleep: Sleep,
+    done: bool,
+}
+
+// The bucket is accounted in units, `per` units making up a token, so that
+// refilling `tokens` units per nanosecond gives the requested rate without
+// rounding.
+#[derive(Debug)]
+struct Inner {
+    timer: Timer,
+    // Tokens refilled every `per`, which is in nanoseconds
+    tokens: u64,
+    per: u64,
+    // Units held by a full bucket
+    capacity: i64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    // Units in the bucket, minus the units reserved by pending acquisitions.
+    // Negative when acquisitions are waiting.
+    units: i64,
+    // The instant `units` was last refilled at
+    refilled: Instant,
+    // Pending acquisitions, linked in FIFO order from `head` to `tail`
+    waiters: Slab<Waiter>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    // Tokens reserved by the pending acquisitions
+    reserved: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    tokens: u64,
+    task: Option<Task>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter refilling `tokens` tokens every `per`, holding
+    /// at most `burst` tokens. The bucket starts full.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `tokens` or `per` is zero.
+    pub fn new(timer: &Timer, tokens: u64, per: Duration, burst: u64) -> RateLimiter {
+        assert!(tokens > 0 && nanos(per) > 0, "refill rate must not be zero");
+
+        let capacity = units(burst, nanos(per));
+
+        RateLimiter {
+            inner: Arc::new(Inner {
+                timer: timer.clone(),
+                tokens: tokens,
+                per: nanos(per),
+                capacity: capacity,
+                state: Mutex::new(State {
+                    units: capacity,
+                    refilled: timer.now(),
+                    waiters: Slab::with_capacity(0),
+                    head: None,
+                    tail: None,
+                    reserved: 0,
+                }),
+            }),
+        }
+    }
+
+    /// Returns a future completing once `tokens` tokens are available.
+    ///
+    /// The tokens are reserved right away, ahead of later acquisitions.
+    /// Requesting more than `burst` tokens is allowed, the acquisition then
+    /// completes once the bucket has been refilled by the difference.
+    pub fn acquire(&self, tokens: u64) -> Acquire {
+        let key = {
+            let mut state = self.inner.state.lock().unwrap();
+            self.inner.refill(&mut state);
+
+            state.units = state.units.saturating_sub(units(tokens, self.inner.per));
+            state.push(tokens)
+        };
+
+        let now = self.inner.timer.now();
+
+        Acquire {
+            limiter: self.clone(),
+            key: key,
+            tokens: tokens,
+            sleep: self.inner.timer.sleep_until(now),
+            done: false,
+        }
+    }
+
+    /// Take `tokens` tokens if they are available right away and no
+    /// acquisition is waiting, returning `true` on success.
+    pub fn try_acquire(&self, tokens: u64) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        self.inner.refill(&mut state);
+
+        let needed = units(tokens, self.inner.per);
+
+        if state.head.is_none() && state.units >= needed {
+            state.units -= needed;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the number of tokens currently available, not counting the
+    /// tokens reserved by waiting acquisitions.
+    pub fn available(&self) -> u64 {
+        let mut state = self.inner.state.lock().unwrap();
+        self.inner.refill(&mut state);
+
+        if state.units > 0 {
+            state.units as u64 / self.inner.per
+        } else {
+            0
+        }
+    }
+}
+
+impl Inner {
+    fn refill(&self, state: &mut State) {
+        let now = self.timer.now();
+
+        if now > state.refilled {
+            let refilled = units(nanos(now - state.refilled), self.tokens);
+
+            state.units = cmp::min(state.units.saturating_add(refilled), self.capacity);
+            state.refilled = now;
+        }
+    }
+}
+
+/// Returns `a * b` units, saturating at `i64::MAX`
+fn units(a: u64, b: u64) -> i64 {
+    cmp::min(a.saturating_mul(b), i64::max_value() as u64) as i64
+}
+
+impl State {
+    /// Add a waiter for `tokens` tokens at the back of the queue, returning
+    /// its key
+    fn push(&mut self, tokens: u64) -> usize {
+        if !self.waiters.has_available() {
+            let amt = cmp::max(self.waiters.len(), 1);
+            self.waiters.reserve_exact(amt);
+        }
+
+        let waiter = Waiter {
+            tokens: tokens,
+            task: None,
+            prev: self.tail,
+            next: None,
+        };
+
+        let key = self.waiters.insert(waiter).ok().expect("slab full");
+
+        match self.tail {
+            Some(tail) => self.waiters[tail].next = Some(key),
+            None => self.head = Some(key),
+        }
+
+        self.tail = Some(key);
+        self.reserved += tokens;
+
+        key
+    }
+
+    /// Remove the waiter stored at `key` from the queue
+    fn remove(&mut self, key: usize) -> Waiter {
+        let waiter = self.waiters.remove(key).expect("acquisition not found");
+
+        match waiter.prev {
+            Some(prev) => self.waiters[prev].next = waiter.next,
+            None => self.head = waiter.next,
+        }
+
+        match waiter.next {
+            Some(next) => self.waiters[next].prev = waiter.prev,
+            None => self.tail = waiter.prev,
+        }
+
+        self.reserved -= waiter.tokens;
+        waiter
+    }
+
+    /// Returns the task of the acquisition at the front of the queue, the
+    /// only one able to complete
+    fn head_task(&self) -> Option<Task> {
+        self.head.and_then(|head| self.waiters[head].task.clone())
+    }
+}
+
+impl Future for Acquire {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<(), TimerError> {
+        if self.done {
+            return Ok(Async::Ready(()));
+        }
+
+        let inner = self.limiter.inner.clone();
+        let mut state = inner.state.lock().unwrap();
+        inner.refill(&mut state);
+
+        // Acquisitions complete in FIFO order. Only the one at the front of
+        // the queue waits on the timer, it notifies the next one when done.
+        if state.head != Some(self.key) {
+            state.waiters[self.key].task = Some(task::current());
+            return Ok(Async::NotReady);
+        }
+
+        // The tokens reserved by later acquisitions are not needed to
+        // complete this one
+        let later = units(state.reserved - self.tokens, inner.per);
+        let balance = state.units.saturating_add(later);
+
+        if balance < 0 {
+            // Round up to the nanosecond the last missing unit is refilled at
+            let missing = 0i64.saturating_sub(balance) as u64;
+            let wait = missing / inner.tokens + if missing % inner.tokens > 0 { 1 } else { 0 };
+            let when = timer::deadline(state.refilled, from_nanos(wait));
+
+            if self.sleep.deadline() != when {
+                self.sleep.reset(when);
+            }
+
+            // The tokens are already reserved, completing on a timer tick
+            // slightly before `when` does not let other acquisitions exceed
+            // the rate.
+            if self.sleep.poll()?.is_not_ready() {
+                state.waiters[self.key].task = Some(task::current());
+                return Ok(Async::NotReady);
+            }
+        }
+
+        state.remove(self.key);
+        self.done = true;
+
+        let task = state.head_task();
+        drop(state);
+
+        if let Some(task) = task {
+            task.notify();
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let inner = &self.limiter.inner;
+        let mut state = inner.state.lock().unwrap();
+        inner.refill(&mut state);
+
+        // Return the reservation. Later acquisitions can now complete sooner.
+        let returned = units(self.tokens, inner.per);
+        state.units = cmp::min(state.units.saturating_add(returned), inner.capacity);
+        state.remove(self.key);
+
+        let task = state.head_task();
+        drop(state);
+
+        if let Some(task) = task {
+            task.notify();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Drives a `Timer` from a `tokio-core` reactor.
+//!
+//! Instead of spawning a thread, the timer's wheel is processed by a
+//! background future spawned on the reactor. The future sets a single reactor
+//! timeout for the next timer deadline, so expirations are handled by the
+//! reactor's own turn.
+
+use {timer, Builder, Timer};
+use clock::Source;
+use worker::Local;
+
+use futures::{Future, Async, Poll};
+use futures::task;
+use tokio_core::reactor::{Handle, Timeout};
+
+use std::sync::Arc;
+
+/// Processes the wheel of a timer bound to a reactor
+struct Background {
+    local: Arc<Local>,
+    clock: Source,
+    timeout: Option<Timeout>,
+    handle: Handle,
+}
+
+pub fn build(builder: Builder, handle: &Handle) -> Timer {
+    let clock = builder.get_clock();
+    let timer = timer::build_local(builder);
+
+    handle.spawn(Background {
+        local: timer::local(&timer),
+        clock: clock,
+        timeout: None,
+        handle: handle.clone(),
+    });
+
+    timer
+}
+
+impl Future for Background {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        // Get notified when a timeout is set before the reactor timeout fires
+        self.local.set_task(task::current());
+
+        loop {
+            if !self.local.is_running() {
+                // All the timer handles have been dropped
+                return Ok(Async::Ready(()));
+            }
+
+            let next = match self.local.turn(self.clock.now()) {
+                Some(next) => next,
+                None => return Ok(Async::NotReady),
+            };
+
+            if let Some(ref mut timeout) = self.timeout {
+                timeout.reset(next);
+            } else {
+                self.timeout = Some(Timeout::new_at(next, &self.handle).map_err(|_| ())?);
+            }
+
+            match self.timeout.as_mut().unwrap().poll() {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(_) => return Err(()),
+            }
+        }
+    }
+}
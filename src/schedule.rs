@@ -0,0 +1,248 @@
+use {timer, Timer, TimerError};
+use interval::nanos;
+
+use futures::{future, Future, Stream, Async, Poll};
+use futures::future::{Executor, Loop};
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+use futures::task::{self, Task};
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// A handle to a closure scheduled on a `Timer`
+///
+/// Created by `Timer::schedule`, `Timer::schedule_at_fixed_rate` and
+/// `Timer::schedule_with_fixed_delay`. Dropping the handle does not cancel the
+/// schedule, call `cancel` to do so.
+#[derive(Debug, Clone)]
+pub struct Scheduled {
+    inner: Arc<Cancel>,
+}
+
+/// The executor configured with `Builder::executor`
+#[derive(Clone)]
+pub struct Spawner(Arc<Executor<BoxFuture> + Send + Sync>);
+
+type BoxFuture = Box<Future<Item = (), Error = ()> + Send>;
+
+#[derive(Debug)]
+struct Cancel {
+    cancelled: AtomicBool,
+    // The task running the schedule
+    task: Mutex<Option<Task>>,
+}
+
+/// Runs a schedule until it completes or is cancelled
+struct Run<F> {
+    future: F,
+    cancel: Arc<Cancel>,
+}
+
+/// A future polled by the thread notifying it, which is the timer thread
+/// when the future waits on timeouts
+struct Spawned {
+    state: Mutex<Option<State>>,
+    // Set when the future must be polled again
+    notified: AtomicBool,
+}
+
+struct State {
+    spawn: Spawn<BoxFuture>,
+    // A handle to the `Spawned` itself, dropped once the future completes
+    handle: NotifyHandle,
+}
+
+/// Run `f` once, after `delay`
+pub fn once<F>(timer: &Timer, delay: Duration, f: F) -> Scheduled
+    where F: FnOnce() + Send + 'static,
+{
+    spawn(timer, timer.sleep(delay).map(move |_| f()))
+}
+
+/// Run `f` every `period`, starting after `initial_delay`
+pub fn fixed_rate<F>(timer: &Timer, initial_delay: Duration, period: Duration, mut f: F) -> Scheduled
+    where F: FnMut() + Send + 'static,
+{
+    assert!(nanos(period) > 0, "schedule period must not be zero");
+
+    let start = timer::deadline(timer.now(), initial_delay);
+    let interval = timer.interval_at(start, period);
+
+    spawn(timer, interval.for_each(move |_| {
+        f();
+        Ok(())
+    }))
+}
+
+/// Run `f` after `initial_delay`, then again `delay` after each run
+/// completes
+pub fn fixed_delay<F>(timer: &Timer, initial_delay: Duration, delay: Duration, f: F) -> Scheduled
+    where F: FnMut() + Send + 'static,
+{
+    let timer2 = timer.clone();
+
+    // `loop_fn` runs the first iteration right away, so it is only created
+    // once the initial delay has elapsed.
+    let runs = timer.sleep(initial_delay).and_then(move |_| {
+        future::loop_fn(f, move |mut f| {
+            f();
+            timer2.sleep(delay).map(move |_| Loop::Continue::<(), F>(f))
+        })
+    });
+
+    spawn(timer, runs)
+}
+
+/// Run `future` on the executor of `timer`, or on the timer thread if none
+/// is configured
+pub fn spawn<F>(timer: &Timer, future: F) -> Scheduled
+    where F: Future<Item = (), Error = TimerError> + Send + 'static,
+{
+    let cancel = Arc::new(Cancel {
+        cancelled: AtomicBool::new(false),
+        task: Mutex::new(None),
+    });
+
+    let run: BoxFuture = Box::new(Run {
+        future: future,
+        cancel: cancel.clone(),
+    });
+
+    let run = match timer::executor(timer) {
+        Some(executor) => {
+            match executor.0.execute(run) {
+                Ok(()) => None,
+                // Fall back to running the future on the timer thread
+                Err(e) => Some(e.into_future()),
+            }
+        }
+        None => Some(run),
+    };
+
+    if let Some(run) = run {
+        let spawned = Arc::new(Spawned {
+            state: Mutex::new(None),
+            notified: AtomicBool::new(false),
+        });
+
+        let handle = NotifyHandle::from(spawned.clone());
+        let mut spawn = executor::spawn(run);
+
+        // The future is not polled on the calling thread. Its task is notified
+        // by the timer instead, which polls it for the first time.
+        let task = spawn.poll_fn_notify(&handle, 0, |_| task::current());
+
+        *spawned.state.lock().unwrap() = Some(State {
+            spawn: spawn,
+            handle: handle,
+        });
+
+        timer::notify_now(timer, task);
+    }
+
+    Scheduled { inner: cancel }
+}
+
+impl Scheduled {
+    /// Cancel the schedule.
+    ///
+    /// The closure is not run anymore, unless it is running at the time of
+    /// the call.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+
+        // Notify outside of the lock, as the task locks it when polled
+        let task = self.inner.task.lock().unwrap().take();
+
+        if let Some(task) = task {
+            task.notify();
+        }
+    }
+
+    /// Returns `true` if the schedule has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl<F> Future for Run<F>
+    where F: Future<Item = (), Error = TimerError>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        *self.cancel.task.lock().unwrap() = Some(task::current());
+
+        if self.cancel.cancelled.load(Ordering::SeqCst) {
+            return Ok(Async::Ready(()));
+        }
+
+        // A timer error ends the schedule, there is nobody to report it to
+        match self.future.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            _ => Ok(Async::Ready(())),
+        }
+    }
+}
+
+impl Notify for Spawned {
+    fn notify(&self, _: usize) {
+        self.notified.store(true, Ordering::SeqCst);
+
+        loop {
+            // If the future is being polled, the thread polling it sees
+            // `notified` and polls it again.
+            let mut state = match self.state.try_lock() {
+                Ok(state) => state,
+                Err(_) => return,
+            };
+
+            if !self.notified.swap(false, Ordering::SeqCst) {
+                return;
+            }
+
+            let done = match *state {
+                Some(ref mut state) => {
+                    let handle = state.handle.clone();
+                    let spawn = &mut state.spawn;
+
+                    // Do not let a panicking closure take the timer thread
+                    // down, the schedule is dropped instead.
+                    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                        spawn.poll_future_notify(&handle, 0)
+                    }));
+
+                    match res {
+                        Ok(Ok(Async::NotReady)) => false,
+                        _ => true,
+                    }
+                }
+                None => return,
+            };
+
+            if done {
+                // The caller holds a handle to `self`, so dropping the
+                // handle stored in the state does not free it.
+                *state = None;
+            }
+        }
+    }
+}
+
+impl Spawner {
+    pub fn new<E>(executor: E) -> Spawner
+        where E: Executor<BoxFuture> + Send + Sync + 'static,
+    {
+        Spawner(Arc::new(executor))
+    }
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Executor")
+    }
+}
@@ -0,0 +1,93 @@
+use {timer, Sleep, Timer, TimerError};
+
+use futures::{Future, Stream, Async, Poll};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A stream yielding the items of an underlying stream at a limited rate
+///
+/// Created by `Timer::throttle` and `Timer::rate_limit`. At most `n` items are
+/// yielded in any window of the configured duration. Items arriving early are
+/// held back, and the underlying stream is not polled until the held item
+/// has been yielded, so no item is dropped.
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct RateLimit<T: Stream> {
+    stream: T,
+    n: usize,
+    per: Duration,
+    sleep: Sleep,
+    // Instants at which the most recent items were yielded, at most `n`
+    yielded: VecDeque<Instant>,
+    // An item held back until the rate allows yielding it
+    item: Option<T::Item>,
+}
+
+/// Create a new `RateLimit`
+pub fn new<T: Stream>(timer: &Timer, stream: T, n: usize, per: Duration) -> RateLimit<T> {
+    assert!(n > 0, "rate limit must allow at least one item");
+
+    RateLimit {
+        stream: stream,
+        n: n,
+        per: per,
+        sleep: timer.sleep_until(timer.now()),
+        yielded: VecDeque::with_capacity(n),
+        item: None,
+    }
+}
+
+impl<T: Stream> RateLimit<T> {
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Consumes the rate limit, returning the underlying stream.
+    ///
+    /// The item held back, if any, is lost.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+}
+
+impl<T, E> Stream for RateLimit<T>
+    where T: Stream<Error = E>,
+          E: From<TimerError>,
+{
+    type Item = T::Item;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<T::Item>, E> {
+        if self.item.is_none() {
+            match try_ready!(self.stream.poll()) {
+                Some(item) => self.item = Some(item),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+
+        if self.yielded.len() == self.n {
+            // The oldest item must leave the window first
+            let when = timer::deadline(self.yielded[0], self.per);
+
+            if self.sleep.deadline() != when {
+                self.sleep.reset(when);
+            }
+
+            try_ready!(self.sleep.poll());
+
+            self.yielded.pop_front();
+        }
+
+        let now = self.sleep.timer().now();
+        self.yielded.push_back(now);
+
+        Ok(Async::Ready(self.item.take()))
+    }
+}
@@ -1,13 +1,20 @@
-use {interval, Interval, Builder, wheel};
-use worker::Worker;
+use {backoff, debounce, interval, schedule, throttle, watchdog, Backoff, BackoffPolicy, Debounce, Interval, Builder, Jitter, RateLimit, Retry, Scheduled, Watchdog, wheel};
+use schedule::Spawner;
+use worker::{Local, Worker};
 use wheel::{Token, Wheel};
 
-use futures::{Future, Stream, Async, Poll};
+use futures::{Future, IntoFuture, Stream, Async, Poll};
 use futures::task::{self, Task};
 
-use std::{fmt, io};
+use std::{cmp, fmt, io, mem};
 use std::error::Error;
-use std::time::{Duration, Instant};
+use std::ops::Add;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Durations are capped to about 30 years when computing a deadline, as
+/// adding a longer one to an `Instant` or a `SystemTime` may overflow.
+const MAX_DURATION_SECS: u64 = 30 * 365 * 86_400;
 
 /// A facility for scheduling timeouts
 #[derive(Clone)]
@@ -21,7 +28,19 @@ pub struct Timer {
 pub struct Sleep {
     timer: Timer,
     when: Instant,
-    handle: Option<(Task, Token)>,
+    handle: Option<Registration>,
+}
+
+/// A timeout registered with the timer on behalf of a `Sleep`
+#[derive(Debug)]
+struct Registration {
+    task: Task,
+    token: Token,
+    // The timer thread recovery count at the time the timeout was set
+    epoch: usize,
+    // The earliest deadline the timeout was registered with. A reset may
+    // reach the timer after the timeout fired at this deadline.
+    earliest: Instant,
 }
 
 /// Allows a given `Future` to execute for a max duration
@@ -36,10 +55,20 @@ pub struct Timeout<T> {
 #[derive(Debug)]
 pub struct TimeoutStream<T> {
     stream: Option<T>,
-    duration: Duration,
+    // `None` when the stream has a fixed deadline
+    duration: Option<Duration>,
     sleep: Sleep,
 }
 
+/// A `Future` that completes once a `Timer` has shut down
+///
+/// Returned by `Timer::shutdown`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Shutdown {
+    timer: Timer,
+}
+
 /// The error type for timer operations.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TimerError {
@@ -47,6 +76,10 @@ pub enum TimerError {
     TooLong,
     /// The timer has reached capacity and cannot support new timeouts.
     NoCapacity,
+    /// The timer has been shut down.
+    Shutdown,
+    /// The timer thread panicked.
+    Poisoned,
 }
 
 /// The error type for timeout operations.
@@ -65,6 +98,58 @@ pub fn build(builder: Builder) -> Timer {
     Timer { worker: worker }
 }
 
+/// Build a `Timer` that does not spawn a thread. Timeouts are fired by
+/// calling `turn`.
+pub fn build_local(builder: Builder) -> Timer {
+    let wheel = Wheel::new(&builder);
+    let worker = Worker::threadless(wheel, builder);
+
+    Timer { worker: worker }
+}
+
+/// Fire all timeouts of a timer built with `build_local` that expired at
+/// `now`, returning the instant of the next timeout.
+pub fn turn(timer: &Timer, now: Instant) -> Option<Instant> {
+    timer.worker.local().turn(now)
+}
+
+/// Returns the precision of the timer.
+pub fn tolerance(timer: &Timer) -> Duration {
+    *timer.worker.tolerance()
+}
+
+/// Returns `duration` with `jitter` applied.
+pub fn jitter(timer: &Timer, duration: Duration, jitter: &Jitter) -> Duration {
+    timer.worker.jitter(duration, jitter)
+}
+
+/// Returns the instant `duration` after `now`, saturating to a deadline that
+/// is never reached in practice for very long durations.
+pub fn deadline<T: Add<Duration, Output = T>>(now: T, duration: Duration) -> T {
+    now + cmp::min(duration, Duration::from_secs(MAX_DURATION_SECS))
+}
+
+/// Notify `task` from the thread processing the timeouts of `timer`, as soon
+/// as possible.
+pub fn notify_now(timer: &Timer, task: Task) {
+    let now = timer.now();
+
+    if let Err(task) = timer.worker.set_timeout(now, task) {
+        // The timer is overloaded, notify the task from the current thread
+        task.notify();
+    }
+}
+
+/// Returns the executor running the closures scheduled on the timer.
+pub fn executor(timer: &Timer) -> Option<Spawner> {
+    timer.worker.executor().cloned()
+}
+
+/// Returns the wheel of a timer built with `build_local`.
+pub fn local(timer: &Timer) -> Arc<Local> {
+    timer.worker.local().clone()
+}
+
 /*
  *
  * ===== Timer =====
@@ -72,9 +157,25 @@ pub fn build(builder: Builder) -> Timer {
  */
 
 impl Timer {
-    /// Returns a future that completes once the given instant has been reached
+    /// Returns a future that completes once the given duration has elapsed
     pub fn sleep(&self, duration: Duration) -> Sleep {
-        Sleep::new(self.clone(), duration)
+        self.sleep_until(deadline(self.now(), duration))
+    }
+
+    /// Returns a future that completes once the given duration, randomized
+    /// by `jitter`, has elapsed.
+    ///
+    /// See `Builder::jitter_seed`.
+    pub fn sleep_jittered(&self, duration: Duration, jitter: Jitter) -> Sleep {
+        self.sleep(self.worker.jitter(duration, &jitter))
+    }
+
+    /// Returns a future that completes once the given instant has been
+    /// reached.
+    ///
+    /// If `deadline` is in the past, the future is already expired.
+    pub fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Sleep::new(self.clone(), deadline)
     }
 
     /// Allow the given future to execute for at most `duration` time.
@@ -85,10 +186,23 @@ impl Timer {
     pub fn timeout<F, E>(&self, future: F, duration: Duration) -> Timeout<F>
         where F: Future<Error = E>,
               E: From<TimeoutError<F>>,
+    {
+        self.timeout_at(future, deadline(self.now(), duration))
+    }
+
+    /// Allow the given future to execute until `deadline`.
+    ///
+    /// If the given future completes before `deadline`, then the `Timeout`
+    /// future will complete with that result. Otherwise, the `Timeout` future
+    /// completes with a `TimeoutError`, immediately if `deadline` is in the
+    /// past.
+    pub fn timeout_at<F, E>(&self, future: F, deadline: Instant) -> Timeout<F>
+        where F: Future<Error = E>,
+              E: From<TimeoutError<F>>,
     {
         Timeout {
             future: Some(future),
-            sleep: self.sleep(duration),
+            sleep: self.sleep_until(deadline),
         }
     }
 
@@ -104,29 +218,209 @@ impl Timer {
     {
         TimeoutStream {
             stream: Some(stream),
-            duration: duration,
+            duration: Some(duration),
             sleep: self.sleep(duration),
         }
     }
 
+    /// Allow the given stream to yield values until `deadline`.
+    ///
+    /// Unlike `timeout_stream`, the deadline is not reset when a value is
+    /// yielded. Once `deadline` is reached, the stream errors with a
+    /// `TimeoutError`, on the first poll if `deadline` is in the past.
+    pub fn timeout_stream_at<T, E>(&self, stream: T, deadline: Instant) -> TimeoutStream<T>
+        where T: Stream<Error = E>,
+              E: From<TimeoutError<T>>,
+    {
+        TimeoutStream {
+            stream: Some(stream),
+            duration: None,
+            sleep: self.sleep_until(deadline),
+        }
+    }
+
     /// Creates a new interval which will fire at `dur` time into the future,
     /// and will repeat every `dur` interval after
     pub fn interval(&self, dur: Duration) -> Interval {
-        interval::new(self.sleep(dur), dur)
+        let now = self.now();
+        interval::new(self.sleep_until(deadline(now, dur)), dur, Some(now))
     }
 
     /// Creates a new interval which will fire at the time specified by `at`,
     /// and then will repeat every `dur` interval after
+    ///
+    /// If `at` is in the past, the ticks scheduled before the current instant
+    /// are missed ticks. See `MissedTickBehavior`.
     pub fn interval_at(&self, at: Instant, dur: Duration) -> Interval {
-        let now = Instant::now();
+        interval::new(self.sleep_until(at), dur, None)
+    }
 
-        let sleep = if at > now {
-            self.sleep(at - now)
-        } else {
-            self.sleep(Duration::from_millis(0))
-        };
+    /// Creates a new interval which will fire at every multiple of `dur` of
+    /// the wall-clock time.
+    ///
+    /// Multiples are counted from the Unix epoch, so a period of one minute
+    /// fires at the start of every minute, and a period of one day fires at
+    /// midnight UTC. The wall clock is checked at least once per second, and
+    /// the interval re-aligns itself when the clock is stepped. Missed ticks
+    /// are always skipped, regardless of the `MissedTickBehavior`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `dur` is zero.
+    pub fn interval_aligned(&self, dur: Duration) -> Interval {
+        interval::aligned(self.clone(), dur)
+    }
+
+    /// Creates a new interval which will fire every `dur` interval, and ends
+    /// after firing `count` times.
+    pub fn interval_count(&self, dur: Duration, count: u64) -> Interval {
+        interval::limit_count(self.interval(dur), count)
+    }
 
-        interval::new(sleep, dur)
+    /// Creates a new interval which will fire every `dur` interval, and ends
+    /// once `deadline` has been reached.
+    ///
+    /// Only ticks scheduled before `deadline` are yielded.
+    pub fn interval_until(&self, dur: Duration, deadline: Instant) -> Interval {
+        interval::limit_deadline(self.interval(dur), deadline)
+    }
+
+    /// Debounce the given stream.
+    ///
+    /// The returned stream yields an item once `stream` has not yielded any
+    /// new item for `duration`. Only the most recent item is yielded, the
+    /// items it replaced are dropped.
+    pub fn debounce<T, E>(&self, stream: T, duration: Duration) -> Debounce<T>
+        where T: Stream<Error = E>,
+              E: From<TimerError>,
+    {
+        debounce::new(self, stream, duration)
+    }
+
+    /// Throttle the given stream.
+    ///
+    /// The returned stream yields the items of `stream`, at least `period`
+    /// apart. Items arriving early are held back, not dropped. This is
+    /// equivalent to `rate_limit(stream, 1, period)`.
+    pub fn throttle<T, E>(&self, stream: T, period: Duration) -> RateLimit<T>
+        where T: Stream<Error = E>,
+              E: From<TimerError>,
+    {
+        self.rate_limit(stream, 1, period)
+    }
+
+    /// Limit the rate of the given stream.
+    ///
+    /// The returned stream yields the items of `stream`, at most `n` of them
+    /// in any window of duration `per`. Up to `n` items can be yielded in a
+    /// burst. Items arriving early are held back, not dropped.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `n` is zero.
+    pub fn rate_limit<T, E>(&self, stream: T, n: usize, per: Duration) -> RateLimit<T>
+        where T: Stream<Error = E>,
+              E: From<TimerError>,
+    {
+        throttle::new(self, stream, n, per)
+    }
+
+    /// Creates a new stream yielding after each delay of `policy`.
+    ///
+    /// The first delay starts when the stream is first polled, and each
+    /// following one when the stream is polled after yielding.
+    pub fn backoff(&self, policy: BackoffPolicy) -> Backoff {
+        backoff::new(self, policy)
+    }
+
+    /// Run the future returned by `factory`, running it again after a delay
+    /// each time it fails.
+    ///
+    /// The delays between attempts follow `policy`. Once the `max_elapsed`
+    /// budget of the policy is exhausted, the returned future fails with
+    /// `RetryError::Exhausted` containing the last error of the operation.
+    /// `factory` is called for the first time right away.
+    pub fn retry<F, R>(&self, factory: F, policy: BackoffPolicy) -> Retry<F, R>
+        where F: FnMut() -> R,
+              R: IntoFuture,
+    {
+        backoff::retry(self, factory, policy)
+    }
+
+    /// Create a watchdog completing once it has not been kicked for
+    /// `duration`.
+    ///
+    /// The watchdog starts as if it had just been kicked. See
+    /// `Watchdog::handle` to kick it from other tasks.
+    pub fn watchdog(&self, duration: Duration) -> Watchdog {
+        watchdog::new(self, duration)
+    }
+
+    /// Run `f` once, after `delay`.
+    ///
+    /// The closure runs on the executor configured with `Builder::executor`,
+    /// or on the timer thread if none is. Closures running on the timer
+    /// thread delay the processing of timeouts, so they should be short.
+    ///
+    /// The closure is not run if the schedule is cancelled first, or if the
+    /// timer fails, for example because it is shut down.
+    pub fn schedule<F>(&self, delay: Duration, f: F) -> Scheduled
+        where F: FnOnce() + Send + 'static,
+    {
+        schedule::once(self, delay, f)
+    }
+
+    /// Run `f` every `period`, starting after `initial_delay`.
+    ///
+    /// Runs are scheduled at a fixed rate from the first one. When a run is
+    /// late, the missed runs happen right away, as with an `Interval`. See
+    /// `schedule` for where the closure runs.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero.
+    pub fn schedule_at_fixed_rate<F>(&self, initial_delay: Duration, period: Duration, f: F) -> Scheduled
+        where F: FnMut() + Send + 'static,
+    {
+        schedule::fixed_rate(self, initial_delay, period, f)
+    }
+
+    /// Run `f` after `initial_delay`, then again `delay` after each run
+    /// completes.
+    ///
+    /// See `schedule` for where the closure runs.
+    pub fn schedule_with_fixed_delay<F>(&self, initial_delay: Duration, delay: Duration, f: F) -> Scheduled
+        where F: FnMut() + Send + 'static,
+    {
+        schedule::fixed_delay(self, initial_delay, delay, f)
+    }
+
+    /// Shut down the timer.
+    ///
+    /// The timer stops processing timeouts. The tasks of all pending
+    /// timeouts are notified, and polling a `Sleep`, `Timeout`,
+    /// `TimeoutStream` or `Interval` backed by this timer returns
+    /// `TimerError::Shutdown` from then on, unless its deadline had already
+    /// been reached.
+    ///
+    /// The returned future completes once the timer thread has exited.
+    pub fn shutdown(&self) -> Shutdown {
+        self.worker.shutdown();
+        Shutdown { timer: self.clone() }
+    }
+
+    /// Returns the current instant according to the timer's clock.
+    ///
+    /// See `Builder::clock`.
+    pub fn now(&self) -> Instant {
+        self.worker.now()
+    }
+
+    /// Returns the current wall-clock time according to the timer's clock.
+    ///
+    /// See `Clock::system_time`.
+    pub fn system_time(&self) -> SystemTime {
+        self.worker.system_time()
     }
 }
 
@@ -150,10 +444,10 @@ impl fmt::Debug for Timer {
 
 impl Sleep {
     /// Create a new `Sleep`
-    fn new(timer: Timer, duration: Duration) -> Sleep {
+    fn new(timer: Timer, when: Instant) -> Sleep {
         Sleep {
             timer: timer,
-            when: Instant::now() + duration,
+            when: when,
             handle: None,
         }
     }
@@ -166,12 +460,44 @@ impl Sleep {
     ///
     /// See the crate docs for more detail.
     pub fn is_expired(&self) -> bool {
-        Instant::now() >= self.when - *self.timer.worker.tolerance()
+        self.timer.now() >= self.when - *self.timer.worker.tolerance()
+    }
+
+    /// Returns the instant at which the `Sleep` completes
+    pub fn deadline(&self) -> Instant {
+        self.when
+    }
+
+    /// Reset the `Sleep` to complete at `when` instead.
+    ///
+    /// The timeout registered with the timer is rescheduled in place, so
+    /// resetting a `Sleep` is cheaper than dropping it and creating a new
+    /// one. A `Sleep` that has already completed can be reset and polled
+    /// again.
+    pub fn reset(&mut self, when: Instant) {
+        let prev = mem::replace(&mut self.when, when);
+
+        if let Some(ref mut handle) = self.handle {
+            if self.timer.worker.reset_timeout(handle.token, prev, when).is_ok() {
+                if when < handle.earliest {
+                    handle.earliest = when;
+                }
+
+                return;
+            }
+        }
+
+        // The timer is overloaded. Drop the timeout and let the task register
+        // a new one.
+        if let Some(handle) = self.handle.take() {
+            self.timer.worker.cancel_timeout(handle.token, prev);
+            handle.task.notify();
+        }
     }
 
     /// Returns the duration remaining
     pub fn remaining(&self) -> Duration {
-        let now = Instant::now();
+        let now = self.timer.now();
 
         if now >= self.when {
             Duration::from_millis(0)
@@ -195,6 +521,35 @@ impl Future for Sleep {
             return Ok(Async::Ready(()));
         }
 
+        if self.timer.worker.is_poisoned() {
+            return Err(TimerError::Poisoned);
+        }
+
+        if self.timer.worker.is_shutdown() {
+            return Err(TimerError::Shutdown);
+        }
+
+        let epoch = self.timer.worker.epoch();
+
+        // The timer thread recovered from a panic and lost the timeout
+        if self.handle.as_ref().map(|h| h.epoch != epoch).unwrap_or(false) {
+            self.handle = None;
+        }
+
+        // The `Sleep` was reset to a later instant, but the timeout may have
+        // fired at the original instant before the timer received the reset.
+        // In that case, the timeout has to be set again.
+        let now = self.timer.now();
+        let tolerance = *self.timer.worker.tolerance();
+
+        if let Some(handle) = self.handle.take() {
+            if now >= handle.earliest - tolerance {
+                self.timer.worker.cancel_timeout(handle.token, self.when);
+            } else {
+                self.handle = Some(handle);
+            }
+        }
+
         // The `Sleep` has not expired, so perform any necessary operations
         // with the timer worker in order to get notified after the requested
         // instant.
@@ -204,8 +559,10 @@ impl Future for Sleep {
                 // An wakeup request has not yet been sent to the timer. Before
                 // doing so, check to ensure that the requested duration does
                 // not exceed the `max_timeout` duration
-                if (self.when - Instant::now()) > *self.timer.worker.max_timeout() {
-                    return Err(TimerError::TooLong);
+                if let Some(max) = self.timer.worker.max_timeout() {
+                    if (self.when - now) > max {
+                        return Err(TimerError::TooLong);
+                    }
                 }
 
                 // Get the current task handle
@@ -213,7 +570,18 @@ impl Future for Sleep {
 
                 match self.timer.worker.set_timeout(self.when, task.clone()) {
                     Ok(token) => {
-                        (task, token)
+                        // The timer may have shut down before receiving the
+                        // timeout, in which case it never notifies the task.
+                        if self.timer.worker.is_shutdown() {
+                            task.notify();
+                        }
+
+                        Registration {
+                            task: task,
+                            token: token,
+                            epoch: epoch,
+                            earliest: self.when,
+                        }
                     }
                     Err(task) => {
                         // The timer is overloaded, yield the current task
@@ -222,8 +590,8 @@ impl Future for Sleep {
                     }
                 }
             }
-            Some((ref task, token)) => {
-                if task.will_notify_current() {
+            Some(ref handle) => {
+                if handle.task.will_notify_current() {
                     // Nothing more to do, the notify on timeout has already
                     // been registered
                     return Ok(Async::NotReady);
@@ -233,8 +601,15 @@ impl Future for Sleep {
 
                 // The timeout has been moved to another task, in this case the
                 // timer has to be notified
-                match self.timer.worker.move_timeout(token, self.when, task.clone()) {
-                    Ok(_) => (task, token),
+                match self.timer.worker.move_timeout(handle.token, self.when, task.clone()) {
+                    Ok(_) => {
+                        Registration {
+                            task: task,
+                            token: handle.token,
+                            epoch: epoch,
+                            earliest: handle.earliest,
+                        }
+                    }
                     Err(task) => {
                         // Overloaded timer, yield hte current task
                         task.notify();
@@ -253,12 +628,27 @@ impl Future for Sleep {
 
 impl Drop for Sleep {
     fn drop(&mut self) {
-        if let Some((_, token)) = self.handle {
-            self.timer.worker.cancel_timeout(token, self.when);
+        if let Some(ref handle) = self.handle {
+            self.timer.worker.cancel_timeout(handle.token, self.when);
         }
     }
 }
 
+/*
+ *
+ * ===== Shutdown =====
+ *
+ */
+
+impl Future for Shutdown {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        Ok(self.timer.worker.poll_shutdown())
+    }
+}
+
 /*
  *
  * ===== Timeout ====
@@ -380,7 +770,10 @@ impl<T, E> Stream for TimeoutStream<T>
                     Ok(Async::NotReady) => {}
                     Ok(Async::Ready(Some(v))) => {
                         // Reset the timeout
-                        self.sleep = Sleep::new(self.sleep.timer.clone(), self.duration);
+                        if let Some(duration) = self.duration {
+                            let when = deadline(self.sleep.timer.now(), duration);
+                            self.sleep.reset(when);
+                        }
 
                         // Return the value
                         return Ok(Async::Ready(Some(v)));
@@ -425,6 +818,8 @@ impl Error for TimerError {
         match *self {
             TimerError::TooLong => "requested timeout too long",
             TimerError::NoCapacity => "timer out of capacity",
+            TimerError::Shutdown => "timer has shut down",
+            TimerError::Poisoned => "timer thread panicked",
         }
     }
 }
@@ -449,6 +844,8 @@ impl<T> Error for TimeoutError<T> {
         match *self {
             Timer(_, TooLong) => "requested timeout too long",
             Timer(_, NoCapacity) => "timer out of capacity",
+            Timer(_, TimerError::Shutdown) => "timer has shut down",
+            Timer(_, Poisoned) => "timer thread panicked",
             TimedOut(_) => "the future timed out",
         }
     }
@@ -462,6 +859,8 @@ impl<T> From<TimeoutError<T>> for io::Error {
         match src {
             Timer(_, TooLong) => io::Error::new(io::ErrorKind::InvalidInput, "requested timeout too long"),
             Timer(_, NoCapacity) => io::Error::new(io::ErrorKind::Other, "timer out of capacity"),
+            Timer(_, TimerError::Shutdown) => io::Error::new(io::ErrorKind::Other, "timer has shut down"),
+            Timer(_, Poisoned) => io::Error::new(io::ErrorKind::Other, "timer thread panicked"),
             TimedOut(_) => io::Error::new(io::ErrorKind::TimedOut, "the future timed out"),
         }
     }
@@ -0,0 +1,105 @@
+use {timer, Sleep, Timer, TimerError};
+
+use futures::{Future, Async, Poll};
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A future completing once its handles have not been kicked for a given
+/// duration
+///
+/// Created by `Timer::watchdog`. Kicking only records the current instant,
+/// the timeout registered with the timer is moved at most once per
+/// `duration`, when it is reached. Kicking often is therefore cheap and does
+/// not involve the timer thread.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Watchdog {
+    inner: Arc<Inner>,
+    sleep: Sleep,
+}
+
+/// A handle used to kick a `Watchdog`
+///
+/// Created by `Watchdog::handle`. Handles can be cloned and sent to other
+/// threads, any of them keeps the watchdog from completing.
+#[derive(Debug, Clone)]
+pub struct WatchdogHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    timer: Timer,
+    duration: Duration,
+    // The instant of the most recent kick
+    kicked: Mutex<Instant>,
+}
+
+/// Create a new `Watchdog`
+pub fn new(timer: &Timer, duration: Duration) -> Watchdog {
+    let now = timer.now();
+
+    Watchdog {
+        inner: Arc::new(Inner {
+            timer: timer.clone(),
+            duration: duration,
+            kicked: Mutex::new(now),
+        }),
+        sleep: timer.sleep_until(timer::deadline(now, duration)),
+    }
+}
+
+impl Watchdog {
+    /// Returns a handle used to kick the watchdog.
+    pub fn handle(&self) -> WatchdogHandle {
+        WatchdogHandle { inner: self.inner.clone() }
+    }
+
+    /// Postpone the completion of the watchdog to `duration` from now.
+    pub fn kick(&self) {
+        self.inner.kick();
+    }
+
+    /// Returns the duration the watchdog waits for a kick.
+    pub fn duration(&self) -> Duration {
+        self.inner.duration
+    }
+}
+
+impl WatchdogHandle {
+    /// Postpone the completion of the watchdog to `duration` from now.
+    pub fn kick(&self) {
+        self.inner.kick();
+    }
+}
+
+impl Inner {
+    fn kick(&self) {
+        *self.kicked.lock().unwrap() = self.timer.now();
+    }
+
+    fn deadline(&self) -> Instant {
+        timer::deadline(*self.kicked.lock().unwrap(), self.duration)
+    }
+}
+
+impl Future for Watchdog {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<(), TimerError> {
+        loop {
+            try_ready!(self.sleep.poll());
+
+            let deadline = self.inner.deadline();
+
+            if deadline <= self.sleep.deadline() {
+                return Ok(Async::Ready(()));
+            }
+
+            // Kicked since the sleep was set, wait for the remaining time
+            self.sleep.reset(deadline);
+        }
+    }
+}
@@ -1,43 +1,48 @@
-use {Builder};
-use futures::task::Task;
+use {timer, Builder, Timer};
 use slab::Slab;
 use std::{cmp, mem, usize};
 use std::time::{Instant, Duration};
 
-pub struct Wheel {
-    // Actual timer wheel itself.
+/// Number of slots per level of the wheels owned by values driven by a
+/// `Timer`, such as a `DelayQueue`. One is created per value, so they are kept
+/// small.
+const EMBEDDED_NUM_SLOTS: usize = 64;
+
+pub struct Wheel<T> {
+    // The levels of the hierarchical timer wheel.
+    //
+    // Each level is a ring of `num_slots` slots. A slot in level 0 represents
+    // a single tick, a slot in level 1 represents `num_slots` ticks, a slot in
+    // level 2 represents `num_slots^2` ticks, and so on. Enough levels are
+    // allocated to cover the entire `u64` tick range, so a timeout of any
+    // duration can be stored.
     //
-    // Each slot represents a fixed duration of time, and this wheel also
-    // behaves like a ring buffer. All timeouts scheduled will correspond to one
-    // slot and therefore each slot has a linked list of timeouts scheduled in
-    // it. Right now linked lists are done through indices into the `slab`
-    // below.
+    // Timeouts are stored in the lowest level whose slot granularity can
+    // distinguish the timeout from the current tick. As time advances, the
+    // slots of higher levels are emptied and their timeouts are cascaded down
+    // into finer levels until they reach level 0 and fire.
     //
-    // Each slot also contains the next timeout associated with it (the minimum
-    // of the entire linked list).
-    wheel: Vec<Slot>,
+    // Each slot is a linked list of timeouts, done through indices into the
+    // `slab` below.
+    levels: Vec<Level>,
 
     // A slab containing all the timeout entries themselves. This is the memory
     // backing the "linked lists" in the wheel above. Each entry has a prev/next
     // pointer (indices in this array) along with the data associated with the
     // timeout and the time the timeout will fire.
-    slab: Slab<Entry, Token>,
+    slab: Slab<Entry<T>, Token>,
 
     // The instant that this timer was created, through which all other timeout
     // computations are relative to.
     start: Instant,
 
-    // State used during `poll`. The `cur_wheel_tick` field is the current tick
-    // we've poll'd to. That is, all events from `cur_wheel_tick` to the
-    // actual current tick in time still need to be processed.
-    //
-    // The `cur_slab_idx` variable is basically just an iterator over the linked
-    // list associated with a wheel slot. This will get incremented as we move
-    // forward in `poll`
-    cur_wheel_tick: u64,
+    // The last tick that has been fully processed. All timeouts scheduled for
+    // this tick or earlier have been moved to the `pending` list.
+    elapsed: u64,
 
-    // The next timeout to tick
-    cur_slab_idx: Token,
+    // Linked list of timeouts that have expired but have not yet been returned
+    // from `poll`.
+    pending: Token,
 
     // Max capacity of the slab
     max_capacity: usize,
@@ -45,25 +50,38 @@ pub struct Wheel {
     // The duration of each tick in ms
     tick_ms: u64,
 
-    // Mask to convert the current tick to a wheel slot
-    mask: usize,
+    // Number of bits of a tick consumed by each level, `log2(num_slots)`
+    bits: u32,
+
+    // Mask to convert a tick to a slot within a level
+    mask: u64,
 }
 
-#[derive(Clone)]
-struct Slot {
-    head: Token,
-    next_timeout: Option<Instant>,
+struct Level {
+    // Head of the linked list of each slot
+    slots: Vec<Token>,
+
+    // Bit field tracking which slots are occupied, used to find the next
+    // expiration without visiting every slot.
+    occupied: Vec<u64>,
 }
 
-enum Entry {
+enum Entry<T> {
     Reserved,
-    Timeout(Timeout),
+    Timeout(Timeout<T>),
 }
 
-struct Timeout {
-    task: Task,
+struct Timeout<T> {
+    value: T,
+    // The instant requested when the timeout was set. This is used to
+    // identify the timeout when it is moved or cancelled.
     when: Instant,
-    wheel_idx: usize,
+    // The tick at which the timeout fires
+    tick: u64,
+    // Location of the timeout in the wheel. `level` is `PENDING` when the
+    // timeout is in the `pending` list.
+    level: usize,
+    slot: usize,
     prev: Token,
     next: Token,
 }
@@ -72,29 +90,63 @@ struct Timeout {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Token(pub usize);
 
-const EMPTY: Token = Token(usize::MAX);
+pub const EMPTY: Token = Token(usize::MAX);
 
-impl Wheel {
+const PENDING: usize = usize::MAX;
+
+impl<T> Wheel<T> {
     /// Creates a new timer wheel with the given configuration settings.
-    pub fn new(builder: &Builder) -> Wheel {
+    pub fn new(builder: &Builder) -> Wheel<T> {
+        Wheel::with_start(builder, builder.get_clock().now())
+    }
+
+    /// Creates a new timer wheel with the given configuration settings, whose
+    /// ticks are counted from `start`.
+    pub fn with_start(builder: &Builder, start: Instant) -> Wheel<T> {
         let num_slots = builder.get_num_slots();
-        let mask = num_slots - 1;
 
         // Check that the number of slots requested is, in fact, a power of two
-        assert!(num_slots & mask == 0, "num_slots must be a power of two");
+        assert!(num_slots > 1 && num_slots & (num_slots - 1) == 0,
+                "num_slots must be a power of two greater than one");
+
+        let bits = num_slots.trailing_zeros();
+
+        // Enough levels to cover every bit of a `u64` tick
+        let num_levels = (64 + bits - 1) / bits;
+
+        let levels = (0..num_levels)
+            .map(|_| {
+                Level {
+                    slots: vec![EMPTY; num_slots],
+                    occupied: vec![0; (num_slots + 63) / 64],
+                }
+            })
+            .collect();
 
         Wheel {
-            wheel: vec![Slot { head: EMPTY, next_timeout: None }; num_slots],
+            levels: levels,
             slab: Slab::with_capacity(builder.get_initial_capacity()),
-            start: Instant::now(),
-            cur_wheel_tick: 0,
-            cur_slab_idx: EMPTY,
+            start: start,
+            elapsed: 0,
+            pending: EMPTY,
             max_capacity: builder.get_max_capacity(),
-            tick_ms: millis(builder.get_tick_duration()),
-            mask: mask,
+            tick_ms: cmp::max(millis(builder.get_tick_duration()), 1),
+            bits: bits,
+            mask: (num_slots - 1) as u64,
         }
     }
 
+    /// Creates a wheel owned by a value driven by `timer`, with the same
+    /// precision as the timer, starting at the current instant.
+    pub fn embedded(timer: &Timer) -> Wheel<T> {
+        let builder = ::wheel()
+            .tick_duration(timer::tolerance(timer))
+            .num_slots(EMBEDDED_NUM_SLOTS)
+            .max_capacity(usize::MAX);
+
+        Wheel::with_start(&builder, timer.now())
+    }
+
     pub fn available(&self) -> usize {
         self.slab.available()
     }
@@ -102,8 +154,6 @@ impl Wheel {
     /// Reserve a slot in the timer
     pub fn reserve(&mut self) -> Option<Token> {
         // Ensure that there is enough space to reserve a new token.
-        //
-        // TODO: Ensure max capacity is not exceeded
         if self.slab.vacant_entry().is_none() {
             let amt = self.slab.len();
             let amt = cmp::min(amt, self.max_capacity - amt);
@@ -124,49 +174,20 @@ impl Wheel {
         self.slab.remove(token);
     }
 
-    pub fn set_timeout(&mut self, token: Token, mut at: Instant, task: Task) {
-        // First up, figure out where we're gonna go in the wheel. Note that if
-        // we're being scheduled on or before the current wheel tick we just
-        // make sure to defer ourselves to the next tick.
-        let mut tick = self.time_to_ticks(at);
-
-        if tick <= self.cur_wheel_tick {
-            tick = self.cur_wheel_tick + 1;
-        }
-
-        let wheel_idx = self.ticks_to_wheel_idx(tick);
+    pub fn set_timeout(&mut self, token: Token, when: Instant, value: T) {
+        let tick = self.time_to_ticks(when);
 
-        let actual_tick = self.start +
-                          Duration::from_millis(self.tick_ms) * (tick as u32);
+        self.slab[token] = Entry::Timeout(Timeout {
+            value: value,
+            when: when,
+            tick: tick,
+            level: PENDING,
+            slot: 0,
+            prev: EMPTY,
+            next: EMPTY,
+        });
 
-        at = actual_tick;
-
-        // Insert ourselves at the head of the linked list in the wheel.
-        let slot = &mut self.wheel[wheel_idx];
-
-        let prev_head = mem::replace(&mut slot.head, token);
-
-        {
-            self.slab[token] = Entry::Timeout(Timeout {
-                task: task,
-                when: at,
-                wheel_idx: wheel_idx,
-                prev: EMPTY,
-                next: prev_head,
-            });
-        }
-
-        if prev_head != EMPTY {
-            match self.slab[prev_head] {
-                Entry::Timeout(ref mut v) => v.prev = slot.head,
-                _ => panic!("unexpected state"),
-            }
-        }
-
-        // Update the wheel slot's next timeout field.
-        if at <= slot.next_timeout.unwrap_or(at) {
-            slot.next_timeout = Some(at);
-        }
+        self.link(token);
     }
 
     /// Queries this timer to see if any timeouts are ready to fire.
@@ -175,95 +196,82 @@ impl Wheel {
     /// `at`, returning any timeout which has happened up to that point. This
     /// method should be called in a loop until it returns `None` to ensure that
     /// all timeouts are processed.
-    ///
-    /// # Panics
-    ///
-    /// This method will panic if `at` is before the instant that this timer
-    /// wheel was created.
-    pub fn poll(&mut self, at: Instant) -> Option<Task> {
-        let wheel_tick = self.time_to_ticks(at);
-
-        // Advance forward in time to the `wheel_tick` specified.
-        //
-        // TODO: don't visit slots in the wheel more than once
-        while self.cur_wheel_tick <= wheel_tick {
-            let head = self.cur_slab_idx;
-            let idx = self.ticks_to_wheel_idx(self.cur_wheel_tick);
-
-            // If the current slot has no entries or we're done iterating go to
-            // the next tick.
-            if head == EMPTY {
-                if head == self.wheel[idx].head {
-                    self.wheel[idx].next_timeout = None;
-                }
-                self.cur_wheel_tick += 1;
-                let idx = self.ticks_to_wheel_idx(self.cur_wheel_tick);
-                self.cur_slab_idx = self.wheel[idx].head;
-                continue
-            }
+    pub fn poll(&mut self, at: Instant) -> Option<T> {
+        let now = self.time_to_ticks(at);
 
-            // If we're starting to iterate over a slot, clear its timeout as
-            // we're probably going to remove entries. As we skip over each
-            // element of this slot we'll restore the `next_timeout` field if
-            // necessary.
-            if head == self.wheel[idx].head {
-                self.wheel[idx].next_timeout = None;
-            }
+        loop {
+            let head = self.pending;
 
-            // Otherwise, continue iterating over the linked list in the wheel
-            // slot we're on and remove anything which has expired.
-            let head_timeout = {
-                let timeout = self.slab[head].timeout();
-                self.cur_slab_idx = timeout.next;
-                timeout.when
-            };
-
-            if self.time_to_ticks(head_timeout) <= self.time_to_ticks(at) {
-                let task = match self.remove_slab(head) {
-                    Some(Entry::Timeout(v)) => {
-                        Some(v.task)
-                    }
+            if head != EMPTY {
+                return match self.remove_slab(head) {
+                    Some(Entry::Timeout(v)) => Some(v.value),
                     _ => None,
                 };
+            }
 
-                return task;
-            } else {
-                let next = self.wheel[idx].next_timeout.unwrap_or(head_timeout);
-                if head_timeout <= next {
-                    self.wheel[idx].next_timeout = Some(head_timeout);
+            match self.next_expiration() {
+                Some((level, slot, deadline)) if deadline <= now => {
+                    self.elapsed = deadline;
+                    self.process_slot(level, slot);
+                }
+                _ => {
+                    // Nothing is due before `now`, so the wheel can jump
+                    // straight to it. All stored timeouts remain in the same
+                    // level and slot.
+                    if now > self.elapsed {
+                        self.elapsed = now;
+                    }
+
+                    return None;
                 }
             }
         }
-
-        None
     }
 
     /// Returns the instant in time that corresponds to the next timeout
     /// scheduled in this wheel.
     pub fn next_timeout(&self) -> Option<Instant> {
-        // TODO: can this be optimized to not look at the whole array?
-        let mut min = None;
-        for a in self.wheel.iter().filter_map(|s| s.next_timeout.as_ref()) {
-            if let Some(b) = min {
-                if b < a {
-                    continue
-                }
-            }
-            min = Some(a);
+        if self.pending != EMPTY {
+            return Some(self.ticks_to_time(self.elapsed));
         }
 
-        min.map(|t| *t)
+        self.next_expiration()
+            .map(|(_, _, deadline)| self.ticks_to_time(deadline))
     }
 
-    pub fn move_timeout(&mut self, token: Token, when: Instant, task: Task) {
+    pub fn move_timeout(&mut self, token: Token, when: Instant, value: T) {
         match self.slab.get_mut(token) {
             Some(&mut Entry::Timeout(ref mut e)) if e.when == when => {
-                e.task = task;
+                e.value = value;
             }
             _ => {}
         }
     }
 
+    /// Moves the timeout registered at `when` so that it fires at `new`
+    /// instead.
+    ///
+    /// Nothing happens if the timeout already fired. This method completes in
+    /// O(1) time.
+    pub fn reset(&mut self, token: Token, when: Instant, new: Instant) {
+        match self.slab.get(token) {
+            Some(&Entry::Timeout(ref e)) if e.when == when => {}
+            _ => return,
+        }
+
+        let tick = self.time_to_ticks(new);
+
+        self.unlink(token);
+
+        {
+            let timeout = self.slab[token].timeout_mut();
+            timeout.when = new;
+            timeout.tick = tick;
+        }
+
+        self.link(token);
+    }
+
     /// Cancels the specified timeout.
     ///
     /// For timeouts previously registered via `insert` they can be passed back
@@ -284,55 +292,253 @@ impl Wheel {
         self.remove_slab(token);
     }
 
-    fn remove_slab(&mut self, slab_idx: Token) -> Option<Entry> {
-        let mut entry = match self.slab.remove(slab_idx) {
-            Some(e) => e,
-            None => return None,
-        };
+    /// Removes the timeout stored at `token`, returning its value.
+    ///
+    /// Unlike `cancel`, the instant the timeout was set at is not checked.
+    /// `None` is returned if there is no timeout stored at `token`.
+    pub fn remove(&mut self, token: Token) -> Option<T> {
+        match self.slab.get(token) {
+            Some(&Entry::Timeout(_)) => {}
+            _ => return None,
+        }
 
-        if let Entry::Timeout(ref mut entry) = entry {
-            // Remove the node from the linked list
-            if entry.prev == EMPTY {
-                self.wheel[entry.wheel_idx].head = entry.next;
-            } else {
-                self.slab[entry.prev].timeout_mut().next = entry.next;
+        match self.remove_slab(token) {
+            Some(Entry::Timeout(v)) => Some(v.value),
+            _ => None,
+        }
+    }
+
+    /// Returns the instant the timeout stored at `token` was set at.
+    pub fn deadline(&self, token: Token) -> Option<Instant> {
+        match self.slab.get(token) {
+            Some(&Entry::Timeout(ref e)) => Some(e.when),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of timeouts and reserved tokens in the wheel.
+    pub fn len(&self) -> usize {
+        self.slab.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slab.is_empty()
+    }
+
+    /// Removes all timeouts from the wheel, returning their values.
+    ///
+    /// Reserved tokens are kept. This function does not rely on the linked
+    /// lists of the wheel, so it can be used to recover from a panic that
+    /// happened in the middle of updating them.
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut values = vec![];
+
+        for i in 0..self.slab.capacity() {
+            let token = Token(i);
+
+            if let Some(&Entry::Timeout(_)) = self.slab.get(token) {
+                if let Some(Entry::Timeout(v)) = self.slab.remove(token) {
+                    values.push(v.value);
+                }
             }
-            if entry.next != EMPTY {
-                self.slab[entry.next].timeout_mut().prev = entry.prev;
+        }
+
+        for level in &mut self.levels {
+            for slot in &mut level.slots {
+                *slot = EMPTY;
+            }
+
+            for bits in &mut level.occupied {
+                *bits = 0;
+            }
+        }
+
+        self.pending = EMPTY;
+
+        values
+    }
+
+    /// Returns the level, slot and deadline tick of the slot that will be
+    /// processed next.
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        // Timeouts stored in a level always expire before the timeouts stored
+        // in any higher level, so the first occupied level is the one that
+        // holds the next expiration.
+        for (level, lvl) in self.levels.iter().enumerate() {
+            let shift = self.bits as usize * level;
+            let cur = ((self.elapsed >> shift) & self.mask) as usize;
+
+            // All timeouts in a level are stored after the current slot
+            if let Some(slot) = lvl.next_occupied(cur + 1) {
+                let range = shift + self.bits as usize;
+                let base = if range >= 64 {
+                    0
+                } else {
+                    (self.elapsed >> range) << range
+                };
+
+                return Some((level, slot, base | ((slot as u64) << shift)));
             }
+        }
+
+        None
+    }
+
+    /// Empties the given slot, cascading its timeouts down to lower levels or
+    /// moving them to the pending list if they have expired.
+    fn process_slot(&mut self, level: usize, slot: usize) {
+        let mut token = self.levels[level].take(slot);
+
+        while token != EMPTY {
+            let next = self.slab[token].timeout().next;
+            self.link(token);
+            token = next;
+        }
+    }
+
+    /// Links the timeout identified by `token` into the level appropriate
+    /// for its tick.
+    fn link(&mut self, token: Token) {
+        let tick = self.slab[token].timeout().tick;
+
+        let (level, slot, head) = if tick <= self.elapsed {
+            (PENDING, 0, mem::replace(&mut self.pending, token))
+        } else {
+            let level = self.level_for(tick);
+            let shift = self.bits as usize * level;
+            let slot = ((tick >> shift) & self.mask) as usize;
+
+            (level, slot, self.levels[level].push(slot, token))
+        };
+
+        {
+            let timeout = self.slab[token].timeout_mut();
+            timeout.level = level;
+            timeout.slot = slot;
+            timeout.prev = EMPTY;
+            timeout.next = head;
+        }
+
+        if head != EMPTY {
+            self.slab[head].timeout_mut().prev = token;
+        }
+    }
+
+    fn remove_slab(&mut self, slab_idx: Token) -> Option<Entry<T>> {
+        if let Some(&Entry::Timeout(_)) = self.slab.get(slab_idx) {
+            self.unlink(slab_idx);
+        }
+
+        self.slab.remove(slab_idx)
+    }
+
+    /// Remove the timeout stored at `token` from its linked list
+    fn unlink(&mut self, token: Token) {
+        let (level, slot, prev, next) = {
+            let entry = self.slab[token].timeout();
+            (entry.level, entry.slot, entry.prev, entry.next)
+        };
 
-            if self.cur_slab_idx == slab_idx {
-                self.cur_slab_idx = entry.next;
+        if prev == EMPTY {
+            if level == PENDING {
+                self.pending = next;
+            } else {
+                self.levels[level].set_head(slot, next);
             }
+        } else {
+            self.slab[prev].timeout_mut().next = next;
         }
 
-        return Some(entry)
+        if next != EMPTY {
+            self.slab[next].timeout_mut().prev = prev;
+        }
+    }
+
+    /// Returns the level in which a timeout firing at `tick` is stored.
+    ///
+    /// This is the level containing the most significant bit in which `tick`
+    /// differs from the elapsed tick.
+    fn level_for(&self, tick: u64) -> usize {
+        let masked = (self.elapsed ^ tick) | self.mask;
+        let significant = 63 - masked.leading_zeros();
+        (significant / self.bits) as usize
     }
 
     fn time_to_ticks(&self, time: Instant) -> u64 {
-        let dur = time - self.start;
-        let ms = dur.subsec_nanos() as u64 / 1_000_000;
-        let ms = dur.as_secs()
-                    .checked_mul(1_000)
-                    .and_then(|m| m.checked_add(ms))
-                    .expect("overflow scheduling timeout");
-        ms / self.tick_ms
+        if time <= self.start {
+            return 0;
+        }
+
+        millis_floor(time - self.start) / self.tick_ms
+    }
+
+    fn ticks_to_time(&self, ticks: u64) -> Instant {
+        self.start + Duration::from_millis(ticks.saturating_mul(self.tick_ms))
+    }
+}
+
+impl Level {
+    /// Pushes `token` onto the head of the slot's list, returning the
+    /// previous head.
+    fn push(&mut self, slot: usize, token: Token) -> Token {
+        self.occupied[slot / 64] |= 1 << (slot % 64);
+        mem::replace(&mut self.slots[slot], token)
+    }
+
+    fn set_head(&mut self, slot: usize, token: Token) {
+        self.slots[slot] = token;
+
+        if token == EMPTY {
+            self.occupied[slot / 64] &= !(1 << (slot % 64));
+        }
+    }
+
+    /// Empties the slot, returning the head of its list
+    fn take(&mut self, slot: usize) -> Token {
+        self.occupied[slot / 64] &= !(1 << (slot % 64));
+        mem::replace(&mut self.slots[slot], EMPTY)
     }
 
-    fn ticks_to_wheel_idx(&self, ticks: u64) -> usize {
-        (ticks as usize) & self.mask
+    /// Returns the first occupied slot at or after `from`.
+    fn next_occupied(&self, from: usize) -> Option<usize> {
+        let mut word = from / 64;
+
+        if word >= self.occupied.len() {
+            return None;
+        }
+
+        // Ignore the slots before `from` in the first word
+        let mut bits = self.occupied[word] & (!0u64 << (from % 64));
+
+        loop {
+            if bits != 0 {
+                let slot = word * 64 + bits.trailing_zeros() as usize;
+
+                // The last word may be partially used when there are fewer
+                // than 64 slots.
+                return if slot < self.slots.len() { Some(slot) } else { None };
+            }
+
+            word += 1;
+
+            if word == self.occupied.len() {
+                return None;
+            }
+
+            bits = self.occupied[word];
+        }
     }
 }
 
-impl Entry {
-    fn timeout(&self) -> &Timeout {
+impl<T> Entry<T> {
+    fn timeout(&self) -> &Timeout<T> {
         match *self {
             Entry::Timeout(ref v) => v,
             _ => panic!("unexpected state"),
         }
     }
 
-    fn timeout_mut(&mut self) -> &mut Timeout {
+    fn timeout_mut(&mut self) -> &mut Timeout<T> {
         match *self {
             Entry::Timeout(ref mut v) => v,
             _ => panic!("unexpected state"),
@@ -364,3 +570,11 @@ pub fn millis(duration: Duration) -> u64 {
     let millis = (duration.subsec_nanos() + NANOS_PER_MILLI - 1) / NANOS_PER_MILLI;
     duration.as_secs().saturating_mul(MILLIS_PER_SEC).saturating_add(millis as u64)
 }
+
+/// Convert a `Duration` to milliseconds, rounding down and saturating at
+/// `u64::MAX`.
+fn millis_floor(duration: Duration) -> u64 {
+    let millis = duration.subsec_nanos() as u64 / 1_000_000;
+    duration.as_secs().saturating_mul(1_000).saturating_add(millis)
+}
+
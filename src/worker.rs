@@ -1,14 +1,23 @@
 //! This code is needed in order to support a channel that can receive with a
 //! timeout.
+//!
+//! A worker either owns a dedicated thread driving the wheel, or owns the
+//! wheel itself, in which case the wheel is driven by calling `turn`.
 
 use Builder;
+use clock::Source;
+use jitter::{Jitter, Rng};
 use mpmc::Queue;
-use wheel::{Token, Wheel};
-use futures::task::Task;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, Instant};
-use std::thread::{self, Thread};
+use schedule::Spawner;
+use wheel::{self, Token, Wheel};
+use futures::Async;
+use futures::task::{self, Task};
+use std::sync::{Arc, Mutex};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use std::thread::{self, JoinHandle};
 
 #[derive(Clone)]
 pub struct Worker {
@@ -17,16 +26,51 @@ pub struct Worker {
 
 /// Communicate with the timer thread
 struct Tx {
-    chan: Arc<Chan>,
-    worker: Thread,
+    inner: Inner,
     tolerance: Duration,
-    max_timeout: Duration,
+    max_timeout: Option<Duration>,
+    clock: Source,
+    rng: Mutex<Rng>,
+    executor: Option<Spawner>,
+}
+
+enum Inner {
+    /// The wheel is owned by the timer thread
+    Thread {
+        chan: Arc<Chan>,
+        worker: JoinHandle<()>,
+    },
+    /// The wheel is processed by calling `turn`
+    Local(Arc<Local>),
+}
+
+/// A wheel processed by calling `turn`, shared between the timer handles and
+/// the code driving it.
+pub struct Local {
+    run: AtomicBool,
+    state: Mutex<State>,
+}
+
+struct State {
+    wheel: Wheel<Task>,
+    // The next timeout known to the code driving the wheel
+    next: Option<Instant>,
+    // Notified when a timeout is set before `next` or the timer is dropped
+    task: Option<Task>,
 }
 
 struct Chan {
     run: AtomicBool,
     set_timeouts: SetQueue,
     mod_timeouts: ModQueue,
+    // Set once the timer thread has exited
+    exited: AtomicBool,
+    // Tasks waiting for the timer thread to exit
+    waiters: Mutex<Vec<Task>>,
+    // Set when the timer thread panicked and stopped processing timeouts
+    poisoned: AtomicBool,
+    // Incremented each time the timer thread recovers from a panic
+    epoch: AtomicUsize,
 }
 
 /// Messages sent on the `set_timeouts` exchange
@@ -35,6 +79,7 @@ struct SetTimeout(Instant, Task);
 /// Messages sent on the `mod_timeouts` queue
 enum ModTimeout {
     Move(Token, Instant, Task),
+    Reset(Token, Instant, Instant),
     Cancel(Token, Instant),
 }
 
@@ -43,10 +88,14 @@ type ModQueue = Queue<ModTimeout, ()>;
 
 impl Worker {
     /// Spawn a worker, returning a handle to allow communication
-    pub fn spawn(mut wheel: Wheel, builder: Builder) -> Worker {
+    pub fn spawn(mut wheel: Wheel<Task>, builder: Builder) -> Worker {
         let tolerance = builder.get_tick_duration();
         let max_timeout = builder.get_max_timeout();
         let capacity = builder.get_channel_capacity();
+        let clock = builder.get_clock();
+        let respawn = builder.get_respawn_on_panic();
+        let rng = builder.get_rng();
+        let executor = builder.get_executor();
 
         // Assert that the wheel has at least capacity available timeouts
         assert!(wheel.available() >= capacity);
@@ -55,22 +104,54 @@ impl Worker {
             run: AtomicBool::new(true),
             set_timeouts: Queue::with_capacity(capacity, || wheel.reserve().unwrap()),
             mod_timeouts: Queue::with_capacity(capacity, || ()),
+            exited: AtomicBool::new(false),
+            waiters: Mutex::new(vec![]),
+            poisoned: AtomicBool::new(false),
+            epoch: AtomicUsize::new(0),
         });
 
         let chan2 = chan.clone();
+        let clock2 = clock.clone();
 
         // Spawn the worker thread
         let t = thread::Builder::new()
             .name(builder.thread_name.unwrap_or_else(|| "tokio-timer".to_owned()))
-            .spawn(move || run(chan2, wheel))
+            .spawn(move || work(chan2, wheel, clock2, respawn))
             .expect("thread::spawn");
 
         Worker {
             tx: Arc::new(Tx {
-                chan: chan,
-                worker: t.thread().clone(),
+                inner: Inner::Thread {
+                    chan: chan,
+                    worker: t,
+                },
                 tolerance: tolerance,
                 max_timeout: max_timeout,
+                clock: clock,
+                rng: Mutex::new(rng),
+                executor: executor,
+            }),
+        }
+    }
+
+    /// Create a worker that does not spawn a thread. Timeouts are processed
+    /// by calling `Local::turn`.
+    pub fn threadless(wheel: Wheel<Task>, builder: Builder) -> Worker {
+        Worker {
+            tx: Arc::new(Tx {
+                inner: Inner::Local(Arc::new(Local {
+                    run: AtomicBool::new(true),
+                    state: Mutex::new(State {
+                        wheel: wheel,
+                        next: None,
+                        task: None,
+                    }),
+                })),
+                tolerance: builder.get_tick_duration(),
+                max_timeout: builder.get_max_timeout(),
+                clock: builder.get_clock(),
+                rng: Mutex::new(builder.get_rng()),
+                executor: builder.get_executor(),
             }),
         }
     }
@@ -80,53 +161,328 @@ impl Worker {
         &self.tx.tolerance
     }
 
-    pub fn max_timeout(&self) -> &Duration {
-        &self.tx.max_timeout
+    /// The longest timeout that can be requested, if limited
+    pub fn max_timeout(&self) -> Option<Duration> {
+        self.tx.max_timeout
+    }
+
+    /// Returns the current instant according to the timer's clock
+    pub fn now(&self) -> Instant {
+        self.tx.clock.now()
+    }
+
+    /// Returns the current wall-clock time according to the timer's clock
+    pub fn system_time(&self) -> SystemTime {
+        self.tx.clock.system_time()
+    }
+
+    /// Returns `duration` with `jitter` applied, using the timer's random
+    /// number generator
+    pub fn jitter(&self, duration: Duration, jitter: &Jitter) -> Duration {
+        jitter.apply(duration, &mut self.tx.rng.lock().unwrap())
+    }
+
+    /// Returns the executor running scheduled closures, if configured
+    pub fn executor(&self) -> Option<&Spawner> {
+        self.tx.executor.as_ref()
     }
 
     /// Set a timeout
     pub fn set_timeout(&self, when: Instant, task: Task) -> Result<Token, Task> {
-        self.tx.chan.set_timeouts.push(SetTimeout(when, task))
-            .and_then(|ret| {
-                // Unpark the timer thread
-                self.tx.worker.unpark();
-                Ok(ret)
-            })
-            .map_err(|SetTimeout(_, task)| task)
+        match self.tx.inner {
+            Inner::Thread { ref chan, ref worker } => {
+                chan.set_timeouts.push(SetTimeout(when, task))
+                    .and_then(|ret| {
+                        // Unpark the timer thread
+                        worker.thread().unpark();
+                        Ok(ret)
+                    })
+                    .map_err(|SetTimeout(_, task)| task)
+            }
+            Inner::Local(ref local) => local.set_timeout(when, task),
+        }
     }
 
     /// Move a timeout
     pub fn move_timeout(&self, token: Token, when: Instant, task: Task) -> Result<(), Task> {
-        self.tx.chan.mod_timeouts.push(ModTimeout::Move(token, when, task))
-            .and_then(|ret| {
-                self.tx.worker.unpark();
-                Ok(ret)
-            })
-            .map_err(|v| {
-                match v {
-                    ModTimeout::Move(_, _, task) => task,
-                    _ => unreachable!(),
-                }
-            })
+        match self.tx.inner {
+            Inner::Thread { ref chan, ref worker } => {
+                chan.mod_timeouts.push(ModTimeout::Move(token, when, task))
+                    .and_then(|ret| {
+                        worker.thread().unpark();
+                        Ok(ret)
+                    })
+                    .map_err(|v| {
+                        match v {
+                            ModTimeout::Move(_, _, task) => task,
+                            _ => unreachable!(),
+                        }
+                    })
+            }
+            Inner::Local(ref local) => {
+                local.state.lock().unwrap().wheel.move_timeout(token, when, task);
+                Ok(())
+            }
+        }
+    }
 
+    /// Change the instant at which a timeout fires
+    ///
+    /// Fails if the timer is overloaded. If the timeout fires before the timer
+    /// processes the request, it fires at the original instant.
+    pub fn reset_timeout(&self, token: Token, when: Instant, new: Instant) -> Result<(), ()> {
+        match self.tx.inner {
+            Inner::Thread { ref chan, ref worker } => {
+                chan.mod_timeouts.push(ModTimeout::Reset(token, when, new))
+                    .and_then(|ret| {
+                        worker.thread().unpark();
+                        Ok(ret)
+                    })
+                    .map_err(|_| ())
+            }
+            Inner::Local(ref local) => {
+                local.reset_timeout(token, when, new);
+                Ok(())
+            }
+        }
     }
 
     /// Cancel a timeout
     pub fn cancel_timeout(&self, token: Token, instant: Instant) {
-        // The result here is ignored because:
-        //
-        // 1) this fn is only called when the timeout is dropping, so nothing
-        //    can be done with the result
-        // 2) Not being able to cancel a timeout is not a huge deal and only
-        //    results in a spurious wakeup.
-        //
-        let _ = self.tx.chan.mod_timeouts.push(ModTimeout::Cancel(token, instant));
+        match self.tx.inner {
+            Inner::Thread { ref chan, .. } => {
+                // The result here is ignored because:
+                //
+                // 1) this fn is only called when the timeout is dropping, so
+                //    nothing can be done with the result
+                // 2) Not being able to cancel a timeout is not a huge deal and
+                //    only results in a spurious wakeup.
+                //
+                let _ = chan.mod_timeouts.push(ModTimeout::Cancel(token, instant));
+            }
+            Inner::Local(ref local) => {
+                local.state.lock().unwrap().wheel.cancel(token, instant);
+            }
+        }
+    }
+
+    /// Returns true once the timer has been shut down
+    pub fn is_shutdown(&self) -> bool {
+        match self.tx.inner {
+            Inner::Thread { ref chan, .. } => !chan.run.load(Ordering::SeqCst),
+            Inner::Local(ref local) => !local.is_running(),
+        }
+    }
+
+    /// Returns true if the timer thread panicked and stopped processing
+    /// timeouts
+    pub fn is_poisoned(&self) -> bool {
+        match self.tx.inner {
+            Inner::Thread { ref chan, .. } => chan.poisoned.load(Ordering::SeqCst),
+            Inner::Local(_) => false,
+        }
+    }
+
+    /// Returns the number of times the timer thread recovered from a panic.
+    /// Timeouts set before a recovery are lost.
+    pub fn epoch(&self) -> usize {
+        match self.tx.inner {
+            Inner::Thread { ref chan, .. } => chan.epoch.load(Ordering::SeqCst),
+            Inner::Local(_) => 0,
+        }
+    }
+
+    /// Shut down the timer, notifying the tasks of all pending timeouts
+    pub fn shutdown(&self) {
+        self.tx.shutdown();
+    }
+
+    /// Returns `Ready` once the timer has finished shutting down. Otherwise,
+    /// the current task is notified when it has.
+    pub fn poll_shutdown(&self) -> Async<()> {
+        match self.tx.inner {
+            Inner::Thread { ref chan, .. } => {
+                if chan.exited.load(Ordering::SeqCst) {
+                    return Async::Ready(());
+                }
+
+                let mut waiters = chan.waiters.lock().unwrap();
+
+                // Check again now that the lock is held, the thread notifies
+                // the waiters after setting `exited`.
+                if chan.exited.load(Ordering::SeqCst) {
+                    return Async::Ready(());
+                }
+
+                waiters.push(task::current());
+                Async::NotReady
+            }
+            Inner::Local(ref local) => {
+                // The wheel is drained synchronously when shutting down
+                if local.is_running() {
+                    Async::NotReady
+                } else {
+                    Async::Ready(())
+                }
+            }
+        }
+    }
+
+    /// Returns the wheel of a worker that does not own a thread
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the worker owns a timer thread.
+    pub fn local(&self) -> &Arc<Local> {
+        match self.tx.inner {
+            Inner::Local(ref local) => local,
+            Inner::Thread { .. } => panic!("timer is driven by a thread"),
+        }
     }
 }
 
-fn run(chan: Arc<Chan>, mut wheel: Wheel) {
-    while chan.run.load(Ordering::Relaxed) {
-        let now = Instant::now();
+impl Local {
+    /// Returns false once the timer has been shut down or all the timer
+    /// handles have been dropped
+    pub fn is_running(&self) -> bool {
+        self.run.load(Ordering::SeqCst)
+    }
+
+    /// Notify `task` when a timeout is set before the instant last returned
+    /// by `turn`, or when all the timer handles are dropped.
+    #[cfg(feature = "tokio-core")]
+    pub fn set_task(&self, task: Task) {
+        self.state.lock().unwrap().task = Some(task);
+    }
+
+    /// Fire all timeouts that expired at `now`, returning the instant of the
+    /// next timeout.
+    ///
+    /// Timeouts set by the notified tasks are taken into account, as the
+    /// tasks are polled right away when they run on the timer.
+    pub fn turn(&self, now: Instant) -> Option<Instant> {
+        let mut expired = vec![];
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            while let Some(task) = state.wheel.poll(now) {
+                expired.push(task);
+            }
+
+            state.next = state.wheel.next_timeout();
+        }
+
+        // Notify outside of the lock as notifying may re-enter the timer
+        for task in expired {
+            task.notify();
+        }
+
+        self.state.lock().unwrap().next
+    }
+
+    /// Returns the instant of the next timeout
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.state.lock().unwrap().wheel.next_timeout()
+    }
+
+    fn set_timeout(&self, when: Instant, task: Task) -> Result<Token, Task> {
+        let (token, notify) = {
+            let mut state = self.state.lock().unwrap();
+
+            let token = match state.wheel.reserve() {
+                Some(token) => token,
+                None => return Err(task),
+            };
+
+            state.wheel.set_timeout(token, when, task);
+
+            (token, state.update_next(when))
+        };
+
+        if let Some(task) = notify {
+            task.notify();
+        }
+
+        Ok(token)
+    }
+
+    fn reset_timeout(&self, token: Token, when: Instant, new: Instant) {
+        let notify = {
+            let mut state = self.state.lock().unwrap();
+
+            state.wheel.reset(token, when, new);
+            state.update_next(new)
+        };
+
+        if let Some(task) = notify {
+            task.notify();
+        }
+    }
+
+    fn shutdown(&self) {
+        self.run.store(false, Ordering::SeqCst);
+
+        let (task, expired) = {
+            let mut state = self.state.lock().unwrap();
+            (state.task.take(), state.wheel.drain())
+        };
+
+        for task in expired.into_iter().chain(task) {
+            task.notify();
+        }
+    }
+}
+
+impl State {
+    /// Returns the task of the driver if it is waiting for an instant later
+    /// than `when`.
+    fn update_next(&mut self, when: Instant) -> Option<Task> {
+        if self.next.map(|next| when < next).unwrap_or(true) {
+            self.next = Some(when);
+            self.task.clone()
+        } else {
+            None
+        }
+    }
+}
+
+/// Body of the timer thread
+///
+/// Panics raised while processing the wheel are caught and, with `respawn`,
+/// processing resumes on this thread. The thread itself is never replaced.
+fn work(chan: Arc<Chan>, mut wheel: Wheel<Task>, clock: Source, respawn: bool) {
+    while chan.run.load(Ordering::SeqCst) {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            run(&chan, &mut wheel, &clock);
+        }));
+
+        if res.is_ok() {
+            break;
+        }
+
+        // The timer panicked while processing the wheel, which may have been
+        // left in an inconsistent state. All of its timeouts are dropped and
+        // their tasks notified.
+        let tasks = wheel.drain();
+
+        if respawn {
+            // Timeouts set before the panic are set again when polled
+            chan.epoch.fetch_add(1, Ordering::SeqCst);
+        } else {
+            chan.poisoned.store(true, Ordering::SeqCst);
+            chan.run.store(false, Ordering::SeqCst);
+        }
+
+        notify_all(tasks);
+    }
+
+    exit(&chan, &mut wheel);
+}
+
+fn run(chan: &Chan, wheel: &mut Wheel<Task>, clock: &Source) {
+    while chan.run.load(Ordering::SeqCst) {
+        let now = clock.now();
 
         // Fire off all expired timeouts
         while let Some(task) = wheel.poll(now) {
@@ -152,6 +508,9 @@ fn run(chan: Arc<Chan>, mut wheel: Wheel) {
                 Ok((ModTimeout::Move(token, when, task), _)) => {
                     wheel.move_timeout(token, when, task);
                 }
+                Ok((ModTimeout::Reset(token, when, new), _)) => {
+                    wheel.reset(token, when, new);
+                }
                 Ok((ModTimeout::Cancel(token, when), _)) => {
                     wheel.cancel(token, when);
                 }
@@ -160,7 +519,7 @@ fn run(chan: Arc<Chan>, mut wheel: Wheel) {
         }
 
         // Update `now` in case the tick was extra long for some reason
-        let now = Instant::now();
+        let now = clock.now();
 
         if let Some(next) = wheel.next_timeout() {
             if next > now {
@@ -172,9 +531,54 @@ fn run(chan: Arc<Chan>, mut wheel: Wheel) {
     }
 }
 
+fn exit(chan: &Chan, wheel: &mut Wheel<Task>) {
+    // The timer is shutting down, notify the tasks of all timeouts that will
+    // not fire, including the ones still in the queues.
+    let mut tasks = wheel.drain();
+
+    while let Ok((SetTimeout(_, task), _)) = chan.set_timeouts.pop(wheel::EMPTY) {
+        tasks.push(task);
+    }
+
+    while let Ok((msg, _)) = chan.mod_timeouts.pop(()) {
+        if let ModTimeout::Move(_, _, task) = msg {
+            tasks.push(task);
+        }
+    }
+
+    notify_all(tasks);
+
+    chan.exited.store(true, Ordering::SeqCst);
+
+    let waiters = mem::replace(&mut *chan.waiters.lock().unwrap(), vec![]);
+    notify_all(waiters);
+}
+
+/// Notify each of `tasks`, even if notifying one of them panics
+///
+/// A task may be polled right away when notified. A panic would otherwise end
+/// the timer thread without marking it as exited, and the futures waiting for
+/// the shutdown would never complete.
+fn notify_all(tasks: Vec<Task>) {
+    for task in tasks {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| task.notify()));
+    }
+}
+
+impl Tx {
+    fn shutdown(&self) {
+        match self.inner {
+            Inner::Thread { ref chan, ref worker } => {
+                chan.run.store(false, Ordering::SeqCst);
+                worker.thread().unpark();
+            }
+            Inner::Local(ref local) => local.shutdown(),
+        }
+    }
+}
+
 impl Drop for Tx {
     fn drop(&mut self) {
-        self.chan.run.store(false, Ordering::Relaxed);
-        self.worker.unpark();
+        self.shutdown();
     }
 }
@@ -0,0 +1,89 @@
+#![allow(dead_code)]
+
+use futures::{Future, Stream, Poll};
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+pub struct Elapsed {
+    duration: Duration,
+}
+
+const TOLERANCE_MS: u64 = 20;
+
+pub fn time<F: FnOnce()>(f: F) -> Elapsed {
+    let now = Instant::now();
+
+    f();
+
+    Elapsed { duration: now.elapsed() }
+}
+
+impl Elapsed {
+    pub fn assert_is_about(&self, dur: Duration) {
+        let tolerance = Duration::from_millis(TOLERANCE_MS);
+
+        if self.duration > dur {
+            assert!(self.duration - dur <= tolerance, "expect={:?}; actual={:?}", dur, self.duration);
+        } else {
+            assert!(dur - self.duration <= tolerance, "expect={:?}; actual={:?}", dur, self.duration);
+        }
+    }
+}
+
+/// Polls a future or stream from a task that records notifications
+pub struct MockTask<T> {
+    spawn: Spawn<T>,
+    notify: Arc<Flag>,
+}
+
+struct Flag(AtomicBool);
+
+pub fn task<T>(inner: T) -> MockTask<T> {
+    MockTask {
+        spawn: executor::spawn(inner),
+        notify: Arc::new(Flag(AtomicBool::new(false))),
+    }
+}
+
+impl<T> MockTask<T> {
+    /// Returns true if the task has been notified since the last poll
+    pub fn is_notified(&self) -> bool {
+        self.notify.0.load(Ordering::SeqCst)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.spawn.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.spawn.into_inner()
+    }
+
+    fn handle(&self) -> NotifyHandle {
+        self.notify.0.store(false, Ordering::SeqCst);
+        NotifyHandle::from(self.notify.clone())
+    }
+}
+
+impl<T: Future> MockTask<T> {
+    pub fn poll(&mut self) -> Poll<T::Item, T::Error> {
+        let handle = self.handle();
+        self.spawn.poll_future_notify(&handle, 0)
+    }
+}
+
+impl<T: Stream> MockTask<T> {
+    pub fn poll_stream(&mut self) -> Poll<Option<T::Item>, T::Error> {
+        let handle = self.handle();
+        self.spawn.poll_stream_notify(&handle, 0)
+    }
+}
+
+impl Notify for Flag {
+    fn notify(&self, _: usize) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,123 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{future, Async, Stream};
+use timer::{mock, BackoffPolicy, Jitter, RetryError};
+use std::cell::Cell;
+use std::f64;
+use std::time::*;
+
+#[test]
+fn test_backoff_delays() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let policy = BackoffPolicy::new(Duration::from_secs(1))
+        .max_delay(Duration::from_secs(8))
+        .max_elapsed(Duration::from_secs(30));
+
+    let t = timer.clone();
+    let elapsed = timer.run(timer.backoff(policy).map(move |_| t.now() - start).collect()).unwrap();
+
+    let expect: Vec<_> = [1, 3, 7, 15, 23].iter().map(|s| Duration::from_secs(*s)).collect();
+    assert_eq!(expect, elapsed);
+}
+
+#[test]
+fn test_backoff_reset() {
+    let timer = mock::Timer::new();
+    let mut backoff = timer.backoff(BackoffPolicy::new(Duration::from_secs(1)).multiplier(3.0));
+
+    for _ in 0..3 {
+        backoff = timer.run(backoff.into_future()).map_err(|(e, _)| e).unwrap().1;
+    }
+
+    backoff.reset();
+
+    let start = timer.now();
+    timer.run(backoff.into_future()).map_err(|(e, _)| e).unwrap();
+
+    assert_eq!(Duration::from_secs(1), timer.now() - start);
+}
+
+#[test]
+fn test_backoff_very_long_delays() {
+    let timer = mock::Timer::new();
+
+    let policy = BackoffPolicy::new(Duration::from_secs(1))
+        .multiplier(1e30)
+        .max_elapsed(Duration::from_secs(u64::max_value()));
+
+    let backoff = timer.run(timer.backoff(policy).into_future()).map_err(|(e, _)| e).unwrap().1;
+    let mut backoff = support::task(backoff);
+    assert_eq!(Async::NotReady, backoff.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(10 * 365 * 86_400));
+    assert!(!backoff.is_notified());
+
+    // A NaN multiplier does not grow the delay
+    let policy = BackoffPolicy::new(Duration::from_secs(1)).multiplier(f64::NAN);
+
+    let start = timer.now();
+    timer.run(timer.backoff(policy).take(3).collect()).unwrap();
+    assert_eq!(Duration::from_secs(1), timer.now() - start);
+}
+
+#[test]
+fn test_backoff_jitter() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let policy = BackoffPolicy::new(Duration::from_secs(10))
+        .multiplier(1.0)
+        .jitter(Jitter::Proportional(0.1));
+
+    timer.run(timer.backoff(policy).take(10).collect()).unwrap();
+
+    let elapsed = timer.now() - start;
+    assert!(elapsed >= Duration::from_secs(90) && elapsed <= Duration::from_secs(110));
+    assert!(elapsed != Duration::from_secs(100));
+}
+
+#[test]
+fn test_retry_until_success() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let attempts = Cell::new(0);
+
+    let retry = timer.retry(|| {
+        attempts.set(attempts.get() + 1);
+
+        if attempts.get() < 3 {
+            future::err::<u32, &str>("not yet")
+        } else {
+            future::ok(attempts.get())
+        }
+    }, BackoffPolicy::new(Duration::from_secs(1)));
+
+    assert_eq!(Ok(3), timer.run(retry));
+
+    // 1 + 2 seconds of backoff
+    assert_eq!(Duration::from_secs(3), timer.now() - start);
+}
+
+#[test]
+fn test_retry_budget_exhausted() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let attempts = Cell::new(0);
+
+    let policy = BackoffPolicy::new(Duration::from_secs(1))
+        .max_elapsed(Duration::from_secs(10));
+
+    let retry = timer.retry(|| {
+        attempts.set(attempts.get() + 1);
+        Err::<(), u32>(attempts.get())
+    }, policy);
+
+    // Attempts at 0, 1, 3 and 7 seconds
+    assert_eq!(Err(RetryError::Exhausted(4)), timer.run(retry));
+    assert_eq!(Duration::from_secs(7), timer.now() - start);
+}
@@ -0,0 +1,149 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{Async, Stream};
+use timer::mock;
+use timer::cron::{Cron, ParseError, Schedule};
+use std::time::*;
+
+// Friday 14 July 2017, 02:40:00 UTC
+const FRIDAY: u64 = 1_500_000_000;
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn next_after(expr: &str, secs: u64) -> Option<SystemTime> {
+    Schedule::parse(expr).unwrap().next_after(at(secs))
+}
+
+#[test]
+fn test_cron_parse_errors() {
+    assert_eq!(Err(ParseError::FieldCount(4)), Schedule::parse("* * * *"));
+    assert_eq!(Err(ParseError::FieldCount(7)), Schedule::parse("* * * * * * *"));
+
+    let invalid = |field, value: &str| Err(ParseError::InvalidField(field, value.to_owned()));
+
+    assert_eq!(invalid("minute", "60"), Schedule::parse("60 * * * *"));
+    assert_eq!(invalid("hour", "*/0"), Schedule::parse("* */0 * * *"));
+    assert_eq!(invalid("day of month", "0"), Schedule::parse("* * 0 * *"));
+    assert_eq!(invalid("month", "1,FOO"), Schedule::parse("* * * 1,FOO *"));
+    assert_eq!(invalid("day of week", "FRI-MON"), Schedule::parse("* * * * FRI-MON"));
+    assert_eq!(invalid("second", "a"), Schedule::parse("a * * * * *"));
+}
+
+#[test]
+fn test_cron_equivalent_expressions() {
+    let parse = |expr| Schedule::parse(expr).unwrap();
+
+    assert_eq!(parse("0 0 * * 0"), parse("0 0 * * 7"));
+    assert_eq!(parse("0 0 * * 0"), parse("0 0 ? * sun"));
+    assert_eq!(parse("0 0 * * 0"), parse("@weekly"));
+    assert_eq!(parse("0 0 1 1 *"), parse("0 0 0 1 JAN *"));
+    assert_eq!(parse("0 * * * *"), parse("@hourly"));
+    assert_eq!(parse("*/20 * * * *"), parse("0,20,40 * * * *"));
+    assert_eq!(parse("10 * * * *"), parse("10/4294967295 * * * *"));
+    assert_eq!(parse("10/20 * * * *"), parse("10-59/20 * * * *"));
+    assert_eq!(parse("0 9-17 * * MON-FRI"), parse("0 9,10,11,12,13,14,15,16,17 * * 1-5"));
+}
+
+#[test]
+fn test_cron_next_after() {
+    // Strictly after the given time
+    assert_eq!(Some(at(FRIDAY + 300)), next_after("*/15 * * * *", FRIDAY));
+    assert_eq!(Some(at(FRIDAY + 10)), next_after("*/10 * * * * *", FRIDAY + 3));
+    assert_eq!(Some(at(FRIDAY + 1)), next_after("* * * * * *", FRIDAY));
+
+    // Midnight on the next Sunday
+    assert_eq!(Some(at(1_500_163_200)), next_after("0 0 * * 7", FRIDAY));
+
+    // The next month
+    assert_eq!(Some(at(1_501_545_600)), next_after("0 0 1 * *", FRIDAY));
+}
+
+#[test]
+fn test_cron_next_after_leap_day() {
+    // 29 February 2020, then 2024
+    assert_eq!(Some(at(1_582_934_400)), next_after("0 0 29 2 *", FRIDAY));
+    assert_eq!(Some(at(1_709_164_800)), next_after("0 0 29 2 *", 1_582_934_400));
+}
+
+#[test]
+fn test_cron_day_of_month_or_day_of_week() {
+    // Either the 1st of the month or a Monday: Monday 17 July, then Tuesday
+    // 1st August after Monday 31 July
+    assert_eq!(Some(at(1_500_249_600)), next_after("0 0 1 * MON", FRIDAY));
+    assert_eq!(Some(at(1_501_545_600)), next_after("0 0 1 * MON", 1_500_249_600 + 14 * 86_400));
+}
+
+#[test]
+fn test_cron_never_fires() {
+    assert_eq!(None, next_after("0 0 30 2 *", FRIDAY));
+
+    let timer = mock::Timer::new();
+    let cron = Cron::new(&timer, "0 0 30 2 *".parse().unwrap());
+
+    assert_eq!(None, cron.next_occurrence());
+    assert!(timer.run(cron.collect()).unwrap().is_empty());
+}
+
+#[test]
+fn test_cron_stream() {
+    let timer = mock::Timer::new();
+    timer.set_system_time(at(FRIDAY));
+
+    let start = timer.now();
+    let cron = Cron::new(&timer, "*/20 * * * *".parse().unwrap());
+    assert_eq!(Some(at(FRIDAY + 1_200)), cron.next_occurrence());
+
+    let firings = timer.run(cron.take(3).collect()).unwrap();
+
+    assert_eq!(vec![at(FRIDAY + 1_200), at(FRIDAY + 2_400), at(FRIDAY + 3_600)], firings);
+    assert_eq!(Duration::from_secs(3_600), timer.now() - start);
+    assert_eq!(at(FRIDAY + 3_600), timer.system_time());
+}
+
+#[test]
+fn test_cron_clock_stepped_forwards() {
+    let timer = mock::Timer::new();
+    timer.set_system_time(at(FRIDAY));
+
+    let mut cron = support::task(Cron::new(&timer, "0 * * * *".parse().unwrap()));
+    assert_eq!(Async::NotReady, cron.poll_stream().unwrap());
+
+    // Step past the 03:00, 04:00 and 05:00 occurrences
+    timer.set_system_time(at(FRIDAY + 9_000));
+    timer.advance(Duration::from_secs(1));
+
+    assert!(cron.is_notified());
+    assert_eq!(Async::Ready(Some(at(FRIDAY + 1_200))), cron.poll_stream().unwrap());
+
+    // The missed occurrences are skipped
+    let cron = cron.into_inner();
+    assert_eq!(Some(at(FRIDAY + 12_000)), cron.next_occurrence());
+
+    let (firing, _) = timer.run(cron.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(Some(at(FRIDAY + 12_000)), firing);
+    assert_eq!(at(FRIDAY + 12_000), timer.system_time());
+}
+
+#[test]
+fn test_cron_clock_stepped_backwards() {
+    let timer = mock::Timer::new();
+    timer.set_system_time(at(FRIDAY + 1_150));
+
+    let mut cron = support::task(Cron::new(&timer, "0 3 * * *".parse().unwrap()));
+    assert_eq!(Async::NotReady, cron.poll_stream().unwrap());
+
+    // The occurrence is not yielded before the wall clock reaches it
+    timer.set_system_time(at(FRIDAY));
+
+    let start = timer.now();
+    let cron = cron.into_inner();
+    let (firing, _) = timer.run(cron.into_future()).map_err(|(e, _)| e).unwrap();
+
+    assert_eq!(Some(at(FRIDAY + 1_200)), firing);
+    assert_eq!(Duration::from_secs(1_200), timer.now() - start);
+}
@@ -0,0 +1,125 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{Async, Stream};
+use timer::{mock, DelayQueue};
+use std::time::*;
+
+#[test]
+fn test_delay_queue_yields_in_deadline_order() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let mut queue = DelayQueue::new(&timer);
+
+    for &i in &[3, 1, 4, 2, 5] {
+        queue.insert(i, Duration::from_secs(i));
+    }
+
+    assert_eq!(5, queue.len());
+
+    let t = timer.clone();
+    let values = timer.run(queue.map(move |i| (i, (t.now() - start).as_secs())).collect()).unwrap();
+
+    let expect: Vec<_> = (1..6).map(|i| (i, i)).collect();
+    assert_eq!(expect, values);
+}
+
+#[test]
+fn test_delay_queue_notifies_task() {
+    let timer = mock::Timer::new();
+    let mut queue = support::task(DelayQueue::new(&timer));
+
+    queue.get_mut().insert("a", Duration::from_secs(10));
+    assert_eq!(Async::NotReady, queue.poll_stream().unwrap());
+
+    // Inserting an earlier entry wakes the task sooner
+    queue.get_mut().insert("b", Duration::from_secs(5));
+
+    timer.advance(Duration::from_secs(5));
+    assert!(queue.is_notified());
+    assert_eq!(Async::Ready(Some("b")), queue.poll_stream().unwrap());
+    assert_eq!(Async::NotReady, queue.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(5));
+    assert!(queue.is_notified());
+    assert_eq!(Async::Ready(Some("a")), queue.poll_stream().unwrap());
+
+    // The stream ends once the queue is empty
+    assert_eq!(Async::Ready(None), queue.poll_stream().unwrap());
+}
+
+#[test]
+fn test_delay_queue_remove() {
+    let timer = mock::Timer::new();
+    let mut queue = DelayQueue::new(&timer);
+
+    let a = queue.insert("a", Duration::from_secs(1));
+    queue.insert("b", Duration::from_secs(2));
+
+    assert_eq!("a", queue.remove(&a));
+    assert_eq!(1, queue.len());
+
+    assert_eq!(vec!["b"], timer.run(queue.collect()).unwrap());
+}
+
+#[test]
+fn test_delay_queue_reset() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let mut queue = support::task(DelayQueue::new(&timer));
+
+    let key = queue.get_mut().insert("a", Duration::from_secs(1));
+    assert_eq!(Async::NotReady, queue.poll_stream().unwrap());
+
+    queue.get_mut().reset(&key, Duration::from_secs(3));
+    assert_eq!(start + Duration::from_secs(3), queue.get_mut().deadline(&key));
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(Async::NotReady, queue.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(2));
+    assert!(queue.is_notified());
+    assert_eq!(Async::Ready(Some("a")), queue.poll_stream().unwrap());
+}
+
+#[test]
+fn test_delay_queue_very_long_duration() {
+    let timer = mock::Timer::new();
+    let forever = Duration::from_secs(u64::max_value());
+    let mut queue = support::task(DelayQueue::new(&timer));
+
+    queue.get_mut().insert("a", forever);
+    let key = queue.get_mut().insert("b", Duration::from_secs(1));
+    queue.get_mut().reset(&key, forever);
+    queue.get_mut().insert("c", Duration::from_secs(1));
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(Async::Ready(Some("c")), queue.poll_stream().unwrap());
+    assert_eq!(Async::NotReady, queue.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(10 * 365 * 86_400));
+    assert!(!queue.is_notified());
+}
+
+#[test]
+fn test_delay_queue_many_entries() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let mut queue = DelayQueue::new(&timer);
+
+    for i in 0..10_000u64 {
+        queue.insert(i, Duration::from_millis(i * 100));
+    }
+
+    let t = timer.clone();
+    let values = timer.run(queue.map(move |i| (i, t.now() - start)).collect()).unwrap();
+
+    assert_eq!(10_000, values.len());
+
+    for (n, &(i, elapsed)) in values.iter().enumerate() {
+        assert_eq!(n as u64, i);
+        assert_eq!(Duration::from_millis(i * 100), elapsed);
+    }
+}
@@ -0,0 +1,52 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::Async;
+use std::time::*;
+
+#[test]
+fn test_threadless_sleep_fires_on_turn() {
+    let (timer, mut driver) = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .build_threadless();
+
+    assert_eq!(None, driver.turn(timer.now()));
+
+    let dur = Duration::from_millis(100);
+    let mut sleep = support::task(timer.sleep(dur));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    let next = driver.next_timeout().unwrap();
+    assert!(next <= timer.now() + dur);
+
+    // Turning before the deadline does not fire the sleep
+    assert_eq!(Some(next), driver.turn(timer.now()));
+    assert!(!sleep.is_notified());
+
+    while sleep.poll().unwrap().is_not_ready() {
+        let now = timer.now();
+        let next = driver.turn(now).unwrap_or(now);
+
+        if next > now {
+            std::thread::sleep(next - now);
+        }
+    }
+
+    assert!(sleep.get_mut().is_expired());
+    assert_eq!(None, driver.turn(timer.now()));
+}
+
+#[test]
+fn test_threadless_cancel() {
+    let (timer, mut driver) = timer::wheel().build_threadless();
+
+    let mut sleep = support::task(timer.sleep(Duration::from_secs(10)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+    assert!(driver.next_timeout().is_some());
+
+    drop(sleep);
+
+    assert_eq!(None, driver.turn(timer.now()));
+}
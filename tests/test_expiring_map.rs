@@ -0,0 +1,126 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{Async, Stream};
+use timer::{mock, ExpiringMap};
+use std::time::*;
+
+#[test]
+fn test_expiring_map_removes_expired_entries() {
+    let timer = mock::Timer::new();
+    let map = ExpiringMap::new(&timer, Duration::from_secs(10));
+    let mut evictions = support::task(map.evictions());
+
+    map.insert("a", 1);
+    map.insert_with_ttl("b", 2, Duration::from_secs(5));
+    assert_eq!(Async::NotReady, evictions.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(5));
+    assert!(evictions.is_notified());
+    assert_eq!(Async::Ready(Some(("b", 2))), evictions.poll_stream().unwrap());
+    assert_eq!(Async::NotReady, evictions.poll_stream().unwrap());
+
+    assert_eq!(None, map.get(&"b"));
+    assert_eq!(Some(1), map.get(&"a"));
+    assert_eq!(1, map.len());
+
+    timer.advance(Duration::from_secs(5));
+    assert!(evictions.is_notified());
+    assert_eq!(Async::Ready(Some(("a", 1))), evictions.poll_stream().unwrap());
+    assert!(map.is_empty());
+
+    // Entries inserted into an empty map are removed as well
+    assert_eq!(Async::NotReady, evictions.poll_stream().unwrap());
+    map.insert("c", 3);
+    assert!(!evictions.is_notified());
+
+    timer.advance(Duration::from_secs(10));
+    assert!(evictions.is_notified());
+    assert_eq!(Async::Ready(Some(("c", 3))), evictions.poll_stream().unwrap());
+}
+
+#[test]
+fn test_expiring_map_removes_without_evictions() {
+    let timer = mock::Timer::new();
+    let map = ExpiringMap::new(&timer, Duration::from_secs(10));
+    let evictions = map.evictions();
+
+    map.insert("a", 1);
+    map.insert_with_ttl("b", 2, Duration::from_secs(5));
+    assert_eq!(2, map.len());
+
+    // The evictions stream is never polled, the timer removes the entries
+    timer.advance(Duration::from_secs(5));
+    assert_eq!(1, map.len());
+
+    timer.advance(Duration::from_secs(5));
+    assert!(map.is_empty());
+
+    drop(evictions);
+
+    map.insert("c", 3);
+    timer.advance(Duration::from_secs(10));
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_expiring_map_hides_expired_entries() {
+    let timer = mock::Timer::new();
+    let map = ExpiringMap::new(&timer, Duration::from_secs(1));
+
+    map.insert("a", 1);
+    assert!(map.contains_key(&"a"));
+
+    timer.advance(Duration::from_secs(1));
+
+    // Nothing polls the evictions, but the entry is still not returned
+    assert!(!map.contains_key(&"a"));
+    assert_eq!(None, map.get(&"a"));
+    assert_eq!(None, map.insert("a", 2));
+    assert_eq!(Some(2), map.remove(&"a"));
+}
+
+#[test]
+fn test_expiring_map_sliding_expiration() {
+    let timer = mock::Timer::new();
+    let map = ExpiringMap::new(&timer, Duration::from_secs(10)).sliding_expiration(true);
+    let mut evictions = support::task(map.evictions());
+
+    map.insert("a", 1);
+    assert_eq!(Async::NotReady, evictions.poll_stream().unwrap());
+
+    for _ in 0..5 {
+        timer.advance(Duration::from_secs(6));
+        assert_eq!(Some(1), map.get(&"a"));
+        assert_eq!(Async::NotReady, evictions.poll_stream().unwrap());
+    }
+
+    // Checking for the key does not push the deadline back
+    timer.advance(Duration::from_secs(6));
+    assert!(map.contains_key(&"a"));
+
+    timer.advance(Duration::from_secs(4));
+    assert!(evictions.is_notified());
+    assert_eq!(Async::Ready(Some(("a", 1))), evictions.poll_stream().unwrap());
+}
+
+#[test]
+fn test_expiring_map_evictions_end() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let map = ExpiringMap::new(&timer, Duration::from_secs(10));
+    let evictions = map.evictions();
+
+    map.insert("a", 1);
+    map.insert_with_ttl("b", 2, Duration::from_secs(20));
+    map.insert("c", 3);
+    assert_eq!(Some(3), map.remove(&"c"));
+    drop(map);
+
+    let evicted = timer.run(evictions.collect()).unwrap();
+
+    assert_eq!(vec![("a", 1), ("b", 2)], evicted);
+    assert_eq!(Duration::from_secs(20), timer.now() - start);
+}
@@ -0,0 +1,628 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{future, Async, Future, Stream};
+use futures::sync::mpsc;
+use timer::{mock, Jitter, MissedTickBehavior};
+use std::{f64, io};
+use std::time::*;
+
+#[test]
+fn test_mock_sleep_fires_on_advance() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let mut sleep = support::task(timer.sleep(Duration::from_secs(60)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    timer.advance(Duration::from_millis(59_999));
+    assert!(!sleep.is_notified());
+    assert_eq!(start + Duration::from_millis(59_999), timer.now());
+
+    timer.advance(Duration::from_millis(1));
+    assert!(sleep.is_notified());
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+}
+
+#[test]
+fn test_mock_sleep_very_long_duration() {
+    let timer = mock::Timer::new();
+    let forever = Duration::from_secs(u64::max_value());
+
+    let mut sleep = support::task(timer.sleep(forever));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    let mut timeout = support::task(timer.timeout(future::empty::<(), io::Error>(), forever));
+    assert_eq!(Async::NotReady, timeout.poll().unwrap());
+
+    let mut interval = support::task(timer.interval(forever));
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(10 * 365 * 86_400));
+    assert!(!sleep.is_notified());
+    assert!(!timeout.is_notified());
+    assert!(!interval.is_notified());
+}
+
+#[test]
+fn test_mock_sleep_reset_later() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let mut sleep = support::task(timer.sleep(Duration::from_secs(1)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    sleep.get_mut().reset(start + Duration::from_secs(3));
+    assert_eq!(start + Duration::from_secs(3), sleep.get_mut().deadline());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(!sleep.is_notified());
+
+    timer.advance(Duration::from_secs(2));
+    assert!(sleep.is_notified());
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+}
+
+#[test]
+fn test_mock_sleep_reset_earlier() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let mut sleep = support::task(timer.sleep(Duration::from_secs(60)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    sleep.get_mut().reset(start + Duration::from_secs(1));
+
+    timer.advance(Duration::from_secs(1));
+    assert!(sleep.is_notified());
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+}
+
+#[test]
+fn test_mock_sleep_reset_after_completion() {
+    let timer = mock::Timer::new();
+
+    let mut sleep = support::task(timer.sleep(Duration::from_secs(1)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+
+    // The timeout already fired, so polling registers a new one
+    let when = timer.now() + Duration::from_secs(1);
+    sleep.get_mut().reset(when);
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    timer.advance(Duration::from_millis(999));
+    assert!(!sleep.is_notified());
+
+    timer.advance(Duration::from_millis(1));
+    assert!(sleep.is_notified());
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+}
+
+#[test]
+fn test_mock_timeout() {
+    let timer = mock::Timer::new();
+
+    let mut to = support::task(timer.timeout(future::empty::<(), io::Error>(), Duration::from_secs(1)));
+    assert_eq!(Async::NotReady, to.poll().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(to.is_notified());
+
+    let err = to.poll().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[test]
+fn test_mock_timeout_stream() {
+    let timer = mock::Timer::new();
+    let dur = Duration::from_secs(1);
+
+    let (tx, rx) = mpsc::unbounded::<u32>();
+    let rx = rx.map_err(|_| -> io::Error { unreachable!() });
+    let mut s = support::task(timer.timeout_stream(rx, dur));
+
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    timer.advance(Duration::from_millis(500));
+    tx.unbounded_send(1).unwrap();
+    assert_eq!(Async::Ready(Some(1)), s.poll_stream().unwrap());
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    // The timeout was reset when the value was yielded
+    timer.advance(Duration::from_millis(999));
+    assert!(!s.is_notified());
+
+    timer.advance(Duration::from_millis(1));
+    assert!(s.is_notified());
+
+    let err = s.poll_stream().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[test]
+fn test_mock_sleep_until() {
+    let timer = mock::Timer::new();
+    let deadline = timer.now() + Duration::from_secs(5);
+
+    let mut sleep = support::task(timer.sleep_until(deadline));
+    assert_eq!(deadline, sleep.get_mut().deadline());
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    timer.advance(Duration::from_secs(5));
+    assert!(sleep.is_notified());
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+}
+
+#[test]
+fn test_mock_deadlines_in_the_past_are_expired() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    timer.advance(Duration::from_secs(1));
+
+    let mut sleep = support::task(timer.sleep_until(start));
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+
+    let mut to = support::task(timer.timeout_at(future::empty::<(), io::Error>(), start));
+    assert_eq!(io::ErrorKind::TimedOut, to.poll().unwrap_err().kind());
+
+    let rx = future::empty::<(), io::Error>().into_stream();
+    let mut s = support::task(timer.timeout_stream_at(rx, start));
+    assert_eq!(io::ErrorKind::TimedOut, s.poll_stream().unwrap_err().kind());
+}
+
+#[test]
+fn test_mock_timeout_stream_at_is_not_reset() {
+    let timer = mock::Timer::new();
+    let deadline = timer.now() + Duration::from_secs(1);
+
+    let (tx, rx) = mpsc::unbounded::<u32>();
+    let rx = rx.map_err(|_| -> io::Error { unreachable!() });
+    let mut s = support::task(timer.timeout_stream_at(rx, deadline));
+
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    timer.advance(Duration::from_millis(500));
+    tx.unbounded_send(1).unwrap();
+    assert_eq!(Async::Ready(Some(1)), s.poll_stream().unwrap());
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    timer.advance(Duration::from_millis(500));
+    assert!(s.is_notified());
+
+    let err = s.poll_stream().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[test]
+fn test_mock_interval() {
+    let timer = mock::Timer::new();
+    let dur = Duration::from_secs(1);
+
+    let mut interval = support::task(timer.interval(dur));
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    for _ in 0..3 {
+        timer.advance(dur);
+        assert!(interval.is_notified());
+        assert_eq!(Async::Ready(Some(())), interval.poll_stream().unwrap());
+        assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+    }
+}
+
+fn missed_ticks(behavior: MissedTickBehavior) -> (usize, Duration) {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let dur = Duration::from_secs(1);
+
+    let mut interval = timer.interval(dur);
+    interval.set_missed_tick_behavior(behavior);
+
+    let mut interval = support::task(interval);
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    // The consumer is late by two and a half periods
+    timer.advance(Duration::from_millis(3_500));
+
+    let mut ticks = 0;
+
+    while let Async::Ready(Some(())) = interval.poll_stream().unwrap() {
+        ticks += 1;
+    }
+
+    // Time until the next tick
+    timer.run(interval.into_inner().into_future()).map_err(|(e, _)| e).unwrap();
+
+    (ticks, timer.now() - start)
+}
+
+#[test]
+fn test_mock_interval_missed_ticks_burst() {
+    assert_eq!((3, Duration::from_secs(4)), missed_ticks(MissedTickBehavior::Burst));
+}
+
+#[test]
+fn test_mock_interval_missed_ticks_delay() {
+    assert_eq!((1, Duration::from_millis(4_500)), missed_ticks(MissedTickBehavior::Delay));
+}
+
+#[test]
+fn test_mock_interval_missed_ticks_skip() {
+    assert_eq!((1, Duration::from_secs(4)), missed_ticks(MissedTickBehavior::Skip));
+}
+
+#[test]
+fn test_mock_interval_does_not_drift() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let dur = Duration::from_secs(1);
+
+    let mut interval = timer.interval(dur);
+
+    // Each tick is consumed 1ms late
+    for _ in 0..3600 {
+        timer.run(future::poll_fn(|| interval.poll())).unwrap();
+        timer.advance(Duration::from_millis(1));
+    }
+
+    assert_eq!(Duration::from_millis(3_600_001), timer.now() - start);
+}
+
+#[test]
+fn test_mock_interval_ticks() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let dur = Duration::from_secs(1);
+
+    let mut interval = timer.interval(dur);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let mut ticks = support::task(interval.ticks());
+    assert_eq!(Async::NotReady, ticks.poll_stream().unwrap());
+
+    timer.advance(Duration::from_millis(3_500));
+
+    let tick = match ticks.poll_stream().unwrap() {
+        Async::Ready(Some(tick)) => tick,
+        v => panic!("unexpected {:?}", v),
+    };
+
+    assert_eq!(start + dur, tick.scheduled());
+    assert_eq!(start + Duration::from_millis(3_500), tick.actual());
+    assert_eq!(Duration::from_millis(2_500), tick.lateness());
+    assert_eq!(2, tick.missed());
+
+    let tick = timer.run(ticks.into_inner().into_future()).map_err(|(e, _)| e).unwrap().0.unwrap();
+
+    assert_eq!(start + Duration::from_secs(4), tick.scheduled());
+    assert_eq!(start + Duration::from_secs(4), tick.actual());
+    assert_eq!(0, tick.missed());
+}
+
+#[test]
+fn test_mock_interval_set_period() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let mut interval = timer.interval(Duration::from_secs(60));
+    interval.set_period(Duration::from_secs(1));
+    assert_eq!(Duration::from_secs(1), interval.period());
+
+    timer.run(interval.take(3).collect()).unwrap();
+    assert_eq!(Duration::from_secs(3), timer.now() - start);
+}
+
+#[test]
+fn test_mock_interval_reset() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let dur = Duration::from_secs(1);
+
+    let mut interval = support::task(timer.interval(dur));
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    timer.advance(Duration::from_millis(700));
+    interval.get_mut().reset();
+
+    timer.advance(Duration::from_millis(300));
+    assert!(!interval.is_notified());
+
+    timer.advance(Duration::from_millis(700));
+    assert!(interval.is_notified());
+    assert_eq!(Async::Ready(Some(())), interval.poll_stream().unwrap());
+    assert_eq!(Duration::from_millis(1_700), timer.now() - start);
+}
+
+#[test]
+fn test_mock_interval_very_long_period() {
+    let timer = mock::Timer::new();
+    let forever = Duration::from_secs(u64::max_value());
+
+    let mut interval = support::task(timer.interval(Duration::from_secs(1)));
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(Async::Ready(Some(())), interval.poll_stream().unwrap());
+
+    interval.get_mut().set_period(forever);
+    interval.get_mut().reset();
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    let mut aligned = support::task(timer.interval_aligned(forever));
+    assert_eq!(Async::NotReady, aligned.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(10 * 365 * 86_400));
+    assert!(!interval.is_notified());
+
+    // The wall clock is checked again, but the tick is not due
+    assert_eq!(Async::NotReady, aligned.poll_stream().unwrap());
+}
+
+#[test]
+fn test_mock_interval_count() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let ticks = timer.run(timer.interval_count(Duration::from_secs(1), 3).collect()).unwrap();
+
+    assert_eq!(3, ticks.len());
+    assert_eq!(Duration::from_secs(3), timer.now() - start);
+}
+
+#[test]
+fn test_mock_interval_until() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let deadline = start + Duration::from_millis(3_500);
+
+    let ticks = timer.run(timer.interval_until(Duration::from_secs(1), deadline).ticks().collect()).unwrap();
+
+    let scheduled: Vec<_> = ticks.iter().map(|t| t.scheduled() - start).collect();
+    assert_eq!(vec![Duration::from_secs(1), Duration::from_secs(2), Duration::from_secs(3)], scheduled);
+
+    // The stream ends at the deadline, not at the next tick
+    assert_eq!(Duration::from_millis(3_500), timer.now() - start);
+}
+
+#[test]
+fn test_mock_interval_aligned() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let epoch = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+
+    timer.set_system_time(epoch + Duration::from_millis(30_500));
+
+    let interval = timer.interval_aligned(Duration::from_secs(60));
+    let ticks = timer.run(interval.take(2).collect()).unwrap();
+
+    assert_eq!(2, ticks.len());
+    assert_eq!(epoch + Duration::from_secs(120), timer.system_time());
+    assert_eq!(Duration::from_millis(89_500), timer.now() - start);
+}
+
+#[test]
+fn test_mock_interval_aligned_clock_stepped_backwards() {
+    let timer = mock::Timer::new();
+    let epoch = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+
+    timer.set_system_time(epoch + Duration::from_secs(50));
+
+    let mut interval = support::task(timer.interval_aligned(Duration::from_secs(60)));
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    // The tick is not yielded before the wall clock reaches the boundary
+    timer.set_system_time(epoch);
+
+    let start = timer.now();
+    let interval = interval.into_inner();
+    let (_, interval) = timer.run(interval.into_future()).map_err(|(e, _)| e).unwrap();
+
+    assert_eq!(epoch + Duration::from_secs(60), timer.system_time());
+    assert_eq!(Duration::from_secs(60), timer.now() - start);
+
+    // Stepping back by more than a period realigns the interval to the first
+    // boundary after the new time
+    let mut interval = support::task(interval);
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    timer.set_system_time(epoch - Duration::from_millis(3_590_500));
+
+    let start = timer.now();
+    let interval = interval.into_inner();
+    timer.run(interval.into_future()).map_err(|(e, _)| e).unwrap();
+
+    assert_eq!(epoch - Duration::from_secs(3_540), timer.system_time());
+    assert!(timer.now() - start <= Duration::from_secs(52));
+}
+
+#[test]
+fn test_mock_interval_aligned_clock_stepped_forwards() {
+    let timer = mock::Timer::new();
+    let epoch = UNIX_EPOCH + Duration::from_secs(1_500_001_200);
+
+    timer.set_system_time(epoch);
+
+    let mut interval = support::task(timer.interval_aligned(Duration::from_secs(3_600)));
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(10));
+
+    // The boundary is reached within a second of the clock being stepped
+    timer.set_system_time(epoch + Duration::from_millis(3_599_500));
+
+    let start = timer.now();
+    let interval = interval.into_inner().ticks();
+    let tick = timer.run(interval.into_future()).map_err(|(e, _)| e).unwrap().0.unwrap();
+
+    assert!(timer.now() - start <= Duration::from_secs(1));
+    assert_eq!(0, tick.missed());
+    assert_eq!(epoch + Duration::from_secs(3_600), timer.system_time());
+}
+
+#[test]
+fn test_mock_sleep_jittered() {
+    let timer = mock::Timer::new();
+    let dur = Duration::from_secs(10);
+
+    let delays: Vec<_> = (0..100)
+        .map(|_| timer.sleep_jittered(dur, Jitter::Proportional(0.5)).deadline() - timer.now())
+        .collect();
+
+    for delay in &delays {
+        assert!(*delay >= Duration::from_secs(5) && *delay <= Duration::from_secs(15), "{:?}", delay);
+    }
+
+    assert!(delays.iter().any(|d| *d < dur));
+    assert!(delays.iter().any(|d| *d > dur));
+}
+
+#[test]
+fn test_mock_sleep_jittered_very_long_duration() {
+    let timer = mock::Timer::new();
+    let forever = Duration::from_secs(u64::max_value());
+
+    let mut sleep = support::task(timer.sleep_jittered(forever, Jitter::proportional(0.5)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    let mut sleep = support::task(timer.sleep_jittered(forever, Jitter::Uniform(forever)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    // A NaN fraction leaves the duration as is
+    let dur = Duration::from_secs(10);
+    let sleep = timer.sleep_jittered(dur, Jitter::Proportional(f64::NAN));
+    assert_eq!(dur, sleep.deadline() - timer.now());
+}
+
+#[test]
+#[should_panic(expected = "jitter fraction must be a non-negative number")]
+fn test_jitter_proportional_nan() {
+    Jitter::proportional(f64::NAN);
+}
+
+#[test]
+#[should_panic(expected = "jitter fraction must be a non-negative number")]
+fn test_jitter_proportional_negative() {
+    Jitter::proportional(-0.5);
+}
+
+#[test]
+fn test_mock_jitter_is_deterministic() {
+    fn delays(timer: &mock::Timer) -> Vec<Duration> {
+        (0..10)
+            .map(|_| {
+                let sleep = timer.sleep_jittered(Duration::from_secs(1), Jitter::Uniform(Duration::from_millis(500)));
+                sleep.deadline() - timer.now()
+            })
+            .collect()
+    }
+
+    assert_eq!(delays(&mock::Timer::new()), delays(&mock::Timer::new()));
+
+    let seeded = |seed| mock::Timer::from_builder(timer::wheel().jitter_seed(seed));
+    assert_eq!(delays(&seeded(7)), delays(&seeded(7)));
+    assert!(delays(&seeded(7)) != delays(&seeded(8)));
+}
+
+#[test]
+fn test_mock_interval_jitter() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let mut interval = timer.interval(Duration::from_secs(1));
+    interval.set_jitter(Some(Jitter::Uniform(Duration::from_millis(100))));
+
+    let ticks = timer.run(interval.ticks().take(20).collect()).unwrap();
+
+    let mut prev = start;
+    let mut periods = vec![];
+
+    for tick in ticks {
+        periods.push(tick.scheduled() - prev);
+        prev = tick.scheduled();
+    }
+
+    for period in &periods {
+        assert!(*period >= Duration::from_millis(900) && *period <= Duration::from_millis(1_100), "{:?}", period);
+    }
+
+    assert!(periods.iter().any(|p| *p != periods[0]));
+}
+
+#[test]
+fn test_mock_shutdown() {
+    let timer = mock::Timer::new();
+
+    let mut interval = support::task(timer.interval(Duration::from_secs(1)));
+    assert_eq!(Async::NotReady, interval.poll_stream().unwrap());
+
+    let mut shutdown = support::task(timer.shutdown());
+    assert_eq!(Async::Ready(()), shutdown.poll().unwrap());
+
+    assert!(interval.is_notified());
+    assert_eq!(timer::TimerError::Shutdown, interval.poll_stream().unwrap_err());
+}
+
+#[test]
+fn test_mock_max_timeout() {
+    let timer = mock::Timer::from_builder(timer::wheel()
+        .tick_duration(Duration::from_millis(1))
+        .max_timeout(Duration::from_secs(1)));
+
+    let mut sleep = support::task(timer.sleep(Duration::from_secs(2)));
+    assert!(sleep.poll().is_err());
+}
+
+#[test]
+fn test_mock_run_jumps_to_next_timeout() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    timer.run(timer.sleep(Duration::from_secs(24 * 60 * 60))).unwrap();
+
+    assert_eq!(Duration::from_secs(24 * 60 * 60), timer.now() - start);
+}
+
+#[test]
+fn test_mock_run_backoff() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    // Retry with an exponential backoff until 10 minutes have elapsed
+    let retry = future::loop_fn(Duration::from_secs(1), |delay| {
+        let t = timer.clone();
+
+        timer.sleep(delay).map(move |_| {
+            if t.now() - start >= Duration::from_secs(600) {
+                future::Loop::Break(t.now())
+            } else {
+                future::Loop::Continue(delay * 2)
+            }
+        })
+    });
+
+    let wall = Instant::now();
+    let done = timer.run(retry).unwrap();
+
+    // 1 + 2 + 4 + ... + 512 seconds
+    assert_eq!(Duration::from_secs(1023), done - start);
+    assert!(wall.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_mock_run_timeout() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let to = timer.timeout(timer.sleep(Duration::from_secs(20)).map_err(From::from),
+                           Duration::from_secs(10));
+
+    let err: io::Error = timer.run(to).unwrap_err();
+
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    assert_eq!(Duration::from_secs(10), timer.now() - start);
+}
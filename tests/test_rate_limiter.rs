@@ -0,0 +1,223 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{future, Async};
+use timer::{mock, KeyedRateLimiter, RateLimiter};
+use std::time::*;
+
+#[test]
+fn test_rate_limiter_burst() {
+    let timer = mock::Timer::new();
+    let limiter = RateLimiter::new(&timer, 1, Duration::from_secs(1), 3);
+
+    for _ in 0..3 {
+        let mut acquire = support::task(limiter.acquire(1));
+        assert_eq!(Async::Ready(()), acquire.poll().unwrap());
+    }
+
+    let mut acquire = support::task(limiter.acquire(1));
+    assert_eq!(Async::NotReady, acquire.poll().unwrap());
+
+    timer.advance(Duration::from_millis(999));
+    assert!(!acquire.is_notified());
+
+    timer.advance(Duration::from_millis(1));
+    assert!(acquire.is_notified());
+    assert_eq!(Async::Ready(()), acquire.poll().unwrap());
+}
+
+#[test]
+fn test_rate_limiter_rate() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let limiter = RateLimiter::new(&timer, 10, Duration::from_secs(1), 10);
+
+    // 10 tokens right away, then 10 per second
+    let acquires: Vec<_> = (0..50).map(|_| limiter.acquire(1)).collect();
+    timer.run(future::join_all(acquires)).unwrap();
+
+    assert_eq!(Duration::from_secs(4), timer.now() - start);
+}
+
+#[test]
+fn test_rate_limiter_fifo() {
+    let timer = mock::Timer::new();
+    let limiter = RateLimiter::new(&timer, 1, Duration::from_secs(1), 5);
+
+    let mut large = support::task(limiter.acquire(6));
+    let mut small = support::task(limiter.acquire(1));
+
+    assert_eq!(Async::NotReady, large.poll().unwrap());
+    assert_eq!(Async::NotReady, small.poll().unwrap());
+
+    // The small acquisition waits for the large one
+    timer.advance(Duration::from_secs(1));
+    assert!(large.is_notified());
+    assert!(!small.is_notified());
+    assert_eq!(Async::Ready(()), large.poll().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(small.is_notified());
+    assert_eq!(Async::Ready(()), small.poll().unwrap());
+}
+
+#[test]
+fn test_rate_limiter_cancel_returns_reservation() {
+    let timer = mock::Timer::new();
+    let limiter = RateLimiter::new(&timer, 1, Duration::from_secs(1), 1);
+
+    assert!(limiter.try_acquire(1));
+    assert!(!limiter.try_acquire(1));
+
+    let mut first = support::task(limiter.acquire(2));
+    let mut second = support::task(limiter.acquire(1));
+
+    assert_eq!(Async::NotReady, first.poll().unwrap());
+    assert_eq!(Async::NotReady, second.poll().unwrap());
+
+    drop(first);
+    assert!(second.is_notified());
+    assert_eq!(Async::NotReady, second.poll().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(second.is_notified());
+    assert_eq!(Async::Ready(()), second.poll().unwrap());
+    assert_eq!(0, limiter.available());
+}
+
+#[test]
+fn test_rate_limiter_only_notifies_head() {
+    let timer = mock::Timer::new();
+    let limiter = RateLimiter::new(&timer, 1, Duration::from_secs(1), 1);
+
+    assert!(limiter.try_acquire(1));
+
+    let mut first = support::task(limiter.acquire(1));
+    let second = support::task(limiter.acquire(1));
+    let mut third = support::task(limiter.acquire(1));
+
+    assert_eq!(Async::NotReady, first.poll().unwrap());
+    assert_eq!(Async::NotReady, third.poll().unwrap());
+
+    // Cancelling a waiter in the middle of the queue wakes the head only
+    drop(second);
+    assert!(first.is_notified());
+    assert!(!third.is_notified());
+    assert_eq!(Async::NotReady, first.poll().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(!third.is_notified());
+    assert_eq!(Async::Ready(()), first.poll().unwrap());
+
+    // The next waiter is notified once the head completes
+    assert!(third.is_notified());
+    assert_eq!(Async::NotReady, third.poll().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(Async::Ready(()), third.poll().unwrap());
+}
+
+#[test]
+fn test_rate_limiter_try_acquire_respects_waiters() {
+    let timer = mock::Timer::new();
+    let limiter = RateLimiter::new(&timer, 1, Duration::from_secs(1), 2);
+
+    let mut acquire = support::task(limiter.acquire(3));
+    assert_eq!(Async::NotReady, acquire.poll().unwrap());
+
+    timer.advance(Duration::from_secs(5));
+    assert!(!limiter.try_acquire(1));
+
+    assert_eq!(Async::Ready(()), acquire.poll().unwrap());
+    assert!(limiter.try_acquire(1));
+}
+
+#[test]
+fn test_rate_limiter_very_slow_rate() {
+    let timer = mock::Timer::new();
+    let forever = Duration::from_secs(u64::max_value());
+    let limiter = RateLimiter::new(&timer, 1, forever, 1);
+
+    assert!(limiter.try_acquire(1));
+
+    let mut acquire = support::task(limiter.acquire(1));
+    assert_eq!(Async::NotReady, acquire.poll().unwrap());
+
+    timer.advance(Duration::from_secs(10 * 365 * 24 * 60 * 60));
+    assert!(!acquire.is_notified());
+    assert_eq!(0, limiter.available());
+}
+
+#[test]
+fn test_keyed_rate_limiter_check() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let limiter = KeyedRateLimiter::new(&timer, 1, Duration::from_secs(1), 2);
+
+    assert!(limiter.check(&"a").is_ok());
+    assert!(limiter.check(&"a").is_ok());
+    assert_eq!(Err(start + Duration::from_secs(1)), limiter.check(&"a"));
+
+    // Keys are limited independently
+    assert!(limiter.check(&"b").is_ok());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(limiter.check(&"a").is_ok());
+    assert!(limiter.check(&"a").is_err());
+}
+
+#[test]
+fn test_keyed_rate_limiter_acquire() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+    let limiter = KeyedRateLimiter::new(&timer, 2, Duration::from_secs(1), 1);
+
+    let acquires: Vec<_> = (0..5).map(|_| limiter.acquire("a")).collect();
+    timer.run(future::join_all(acquires)).unwrap();
+
+    assert_eq!(Duration::from_secs(2), timer.now() - start);
+
+    let mut acquire = support::task(limiter.acquire("a"));
+    assert_eq!(Async::NotReady, acquire.poll().unwrap());
+
+    timer.advance(Duration::from_millis(500));
+    assert!(acquire.is_notified());
+    assert_eq!(Async::Ready(()), acquire.poll().unwrap());
+}
+
+#[test]
+fn test_keyed_rate_limiter_evicts_idle_keys() {
+    let timer = mock::Timer::new();
+    let limiter = KeyedRateLimiter::new(&timer, 1, Duration::from_secs(1), 5);
+
+    for i in 0..1_000 {
+        assert!(limiter.check(&i).is_ok());
+    }
+
+    assert!(limiter.check(&0).is_ok());
+    assert_eq!(1_000, limiter.len());
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(1, limiter.len());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(limiter.is_empty());
+}
+
+#[test]
+fn test_keyed_rate_limiter_evicts_without_calls() {
+    let timer = mock::Timer::new();
+    let limiter = KeyedRateLimiter::new(&timer, 1, Duration::from_secs(1), 1);
+
+    assert!(limiter.check(&"a").is_ok());
+    assert_eq!(1, limiter.len());
+
+    // The limiter is not used again, the timer drops the key
+    timer.advance(Duration::from_millis(500));
+    assert_eq!(1, limiter.len());
+
+    timer.advance(Duration::from_millis(500));
+    assert!(limiter.is_empty());
+}
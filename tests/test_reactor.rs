@@ -0,0 +1,65 @@
+#![cfg(feature = "tokio-core")]
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::Core;
+use std::time::*;
+
+#[test]
+fn test_reactor_sleep() {
+    let mut core = Core::new().unwrap();
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .build_with_handle(&core.handle());
+
+    let dur = Duration::from_millis(200);
+
+    let elapsed = support::time(|| {
+        core.run(timer.sleep(dur)).unwrap();
+    });
+
+    elapsed.assert_is_about(dur);
+}
+
+#[test]
+fn test_reactor_earlier_sleep_set_later() {
+    let mut core = Core::new().unwrap();
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .build_with_handle(&core.handle());
+
+    let long = timer.sleep(Duration::from_millis(500));
+    core.handle().spawn(long.map_err(|_| ()));
+
+    // Let the reactor wait for the long sleep first
+    core.turn(Some(Duration::from_millis(10)));
+
+    let dur = Duration::from_millis(100);
+
+    let elapsed = support::time(|| {
+        core.run(timer.sleep(dur)).unwrap();
+    });
+
+    elapsed.assert_is_about(dur);
+}
+
+#[test]
+fn test_reactor_interval() {
+    let mut core = Core::new().unwrap();
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .build_with_handle(&core.handle());
+
+    let dur = Duration::from_millis(100);
+
+    let elapsed = support::time(|| {
+        core.run(timer.interval(dur).take(3).collect()).unwrap();
+    });
+
+    elapsed.assert_is_about(dur * 3);
+}
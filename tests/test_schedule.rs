@@ -0,0 +1,234 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+use futures::Future;
+use futures::future::{Executor, ExecuteError};
+use timer::mock;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::*;
+
+fn counter() -> (Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let count = Arc::new(AtomicUsize::new(0));
+    (count.clone(), count)
+}
+
+#[test]
+fn test_schedule_once() {
+    let timer = mock::Timer::new();
+    let (count, count2) = counter();
+
+    timer.schedule(Duration::from_secs(5), move || {
+        count2.fetch_add(1, Ordering::SeqCst);
+    });
+
+    timer.advance(Duration::from_secs(4));
+    assert_eq!(0, count.load(Ordering::SeqCst));
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(1, count.load(Ordering::SeqCst));
+
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(1, count.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_schedule_cancel() {
+    let timer = mock::Timer::new();
+    let (count, count2) = counter();
+
+    let scheduled = timer.schedule(Duration::from_secs(5), move || {
+        count2.fetch_add(1, Ordering::SeqCst);
+    });
+
+    timer.advance(Duration::from_secs(4));
+    scheduled.cancel();
+    assert!(scheduled.is_cancelled());
+
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(0, count.load(Ordering::SeqCst));
+
+    // The closure has been dropped
+    assert!(Arc::try_unwrap(count).is_ok());
+}
+
+#[test]
+fn test_schedule_at_fixed_rate() {
+    let timer = mock::Timer::new();
+    let (count, count2) = counter();
+
+    let scheduled = timer.schedule_at_fixed_rate(Duration::from_secs(1), Duration::from_secs(2), move || {
+        count2.fetch_add(1, Ordering::SeqCst);
+    });
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(1, count.load(Ordering::SeqCst));
+
+    timer.advance(Duration::from_secs(2));
+    assert_eq!(2, count.load(Ordering::SeqCst));
+
+    // Missed runs happen right away
+    timer.advance(Duration::from_secs(6));
+    assert_eq!(5, count.load(Ordering::SeqCst));
+
+    scheduled.cancel();
+    timer.advance(Duration::from_secs(6));
+    assert_eq!(5, count.load(Ordering::SeqCst));
+}
+
+#[test]
+#[should_panic(expected = "schedule period must not be zero")]
+fn test_schedule_at_fixed_rate_zero_period() {
+    let timer = mock::Timer::new();
+    timer.schedule_at_fixed_rate(Duration::from_secs(1), Duration::new(0, 0), || {});
+}
+
+#[test]
+fn test_schedule_with_fixed_delay() {
+    let timer = mock::Timer::new();
+    let (count, count2) = counter();
+
+    let scheduled = timer.schedule_with_fixed_delay(Duration::from_secs(1), Duration::from_secs(2), move || {
+        count2.fetch_add(1, Ordering::SeqCst);
+    });
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(1, count.load(Ordering::SeqCst));
+
+    timer.advance(Duration::from_secs(2));
+    assert_eq!(2, count.load(Ordering::SeqCst));
+
+    // The next delay starts after the late run
+    timer.advance(Duration::from_secs(6));
+    assert_eq!(3, count.load(Ordering::SeqCst));
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(3, count.load(Ordering::SeqCst));
+
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(4, count.load(Ordering::SeqCst));
+
+    scheduled.cancel();
+    timer.advance(Duration::from_secs(6));
+    assert_eq!(4, count.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_schedule_runs_on_timer_thread() {
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .thread_name("my-timer")
+        .build();
+
+    let (tx, rx) = mpsc::channel();
+
+    timer.schedule(Duration::from_millis(50), move || {
+        tx.send(thread::current().name().map(|s| s.to_owned())).unwrap();
+    });
+
+    assert_eq!(Some("my-timer".to_owned()), rx.recv().unwrap());
+}
+
+#[test]
+fn test_schedule_short_delays_run_on_timer_thread() {
+    // The default tick is 100ms, so all of these are due right away
+    let timer = timer::wheel()
+        .thread_name("my-timer")
+        .build();
+
+    let (tx, rx) = mpsc::channel();
+    let tx2 = tx.clone();
+    let tx3 = tx.clone();
+    let tx4 = tx.clone();
+
+    timer.schedule(Duration::from_millis(0), move || {
+        tx.send(thread::current().name().map(|s| s.to_owned())).unwrap();
+    });
+
+    timer.schedule(Duration::from_millis(90), move || {
+        tx2.send(thread::current().name().map(|s| s.to_owned())).unwrap();
+    });
+
+    let fixed_rate = timer.schedule_at_fixed_rate(Duration::from_millis(0), Duration::from_secs(60), move || {
+        tx3.send(thread::current().name().map(|s| s.to_owned())).unwrap();
+    });
+
+    let fixed_delay = timer.schedule_with_fixed_delay(Duration::from_millis(0), Duration::from_secs(60), move || {
+        tx4.send(thread::current().name().map(|s| s.to_owned())).unwrap();
+    });
+
+    for _ in 0..4 {
+        assert_eq!(Some("my-timer".to_owned()), rx.recv().unwrap());
+    }
+
+    fixed_rate.cancel();
+    fixed_delay.cancel();
+}
+
+#[test]
+fn test_schedule_does_not_run_on_caller() {
+    let timer = mock::Timer::new();
+    let (count, count2) = counter();
+
+    timer.schedule(Duration::from_millis(0), move || {
+        count2.fetch_add(1, Ordering::SeqCst);
+    });
+
+    // The closure runs on the thread driving the timer
+    assert_eq!(0, count.load(Ordering::SeqCst));
+
+    timer.advance(Duration::from_millis(0));
+    assert_eq!(1, count.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_schedule_survives_panic() {
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .build();
+
+    timer.schedule(Duration::from_millis(10), || panic!("scheduled panic"));
+
+    let (tx, rx) = mpsc::channel();
+
+    timer.schedule(Duration::from_millis(50), move || {
+        tx.send(()).unwrap();
+    });
+
+    rx.recv().unwrap();
+}
+
+struct ThreadExecutor;
+
+impl<F> Executor<F> for ThreadExecutor
+    where F: Future<Item = (), Error = ()> + Send + 'static,
+{
+    fn execute(&self, future: F) -> Result<(), ExecuteError<F>> {
+        thread::Builder::new()
+            .name("executor".to_owned())
+            .spawn(move || future.wait())
+            .unwrap();
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_schedule_on_executor() {
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .executor(ThreadExecutor)
+        .build();
+
+    let (tx, rx) = mpsc::channel();
+
+    timer.schedule_with_fixed_delay(Duration::from_millis(10), Duration::from_millis(10), move || {
+        tx.send(thread::current().name().map(|s| s.to_owned())).unwrap();
+    });
+
+    for _ in 0..3 {
+        assert_eq!(Some("executor".to_owned()), rx.recv().unwrap());
+    }
+}
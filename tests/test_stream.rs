@@ -0,0 +1,172 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{stream, Async, Future, Stream};
+use futures::sync::mpsc;
+use timer::mock;
+use std::io;
+use std::time::*;
+
+type Rx = stream::MapErr<mpsc::UnboundedReceiver<u32>, fn(()) -> io::Error>;
+
+fn channel() -> (mpsc::UnboundedSender<u32>, Rx) {
+    fn never(_: ()) -> io::Error {
+        unreachable!()
+    }
+
+    let (tx, rx) = mpsc::unbounded();
+    (tx, rx.map_err(never as fn(()) -> io::Error))
+}
+
+#[test]
+fn test_debounce_yields_most_recent_item() {
+    let timer = mock::Timer::new();
+    let dur = Duration::from_millis(100);
+
+    let (tx, rx) = channel();
+    let mut s = support::task(timer.debounce(rx, dur));
+
+    for i in 0..5 {
+        tx.unbounded_send(i).unwrap();
+        assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+        // Each item arrives before the quiet period is over
+        timer.advance(Duration::from_millis(60));
+        assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+    }
+
+    timer.advance(Duration::from_millis(40));
+    assert!(s.is_notified());
+    assert_eq!(Async::Ready(Some(4)), s.poll_stream().unwrap());
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    // Nothing more is yielded without new items
+    timer.advance(Duration::from_secs(1));
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+}
+
+#[test]
+fn test_debounce_flushes_on_end() {
+    let timer = mock::Timer::new();
+
+    let (tx, rx) = channel();
+    let mut s = support::task(timer.debounce(rx, Duration::from_secs(1)));
+
+    tx.unbounded_send(1).unwrap();
+    tx.unbounded_send(2).unwrap();
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    drop(tx);
+    assert_eq!(Async::Ready(Some(2)), s.poll_stream().unwrap());
+    assert_eq!(Async::Ready(None), s.poll_stream().unwrap());
+}
+
+#[test]
+fn test_debounce_very_long_duration() {
+    let timer = mock::Timer::new();
+
+    let (tx, rx) = channel();
+    let mut s = support::task(timer.debounce(rx, Duration::from_secs(u64::max_value())));
+
+    tx.unbounded_send(1).unwrap();
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(10 * 365 * 86_400));
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    drop(tx);
+    assert_eq!(Async::Ready(Some(1)), s.poll_stream().unwrap());
+}
+
+#[test]
+fn test_debounce_run() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    // Bursts of three items, one second apart, separated by 10 seconds
+    let t = timer.clone();
+    let source = futures::stream::iter_ok::<_, io::Error>(0..9)
+        .and_then(move |i| {
+            let delay = if i % 3 == 0 { 10 } else { 1 };
+            t.sleep(Duration::from_secs(delay)).map_err(io::Error::from).map(move |_| i)
+        });
+
+    let debounced = timer.run(timer.debounce(source, Duration::from_secs(5)).collect()).unwrap();
+
+    assert_eq!(vec![2, 5, 8], debounced);
+
+    // The last item is yielded as soon as the source ends
+    assert_eq!(Duration::from_secs(36), timer.now() - start);
+}
+
+#[test]
+fn test_throttle_spaces_items() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let t = timer.clone();
+    let source = futures::stream::iter_ok::<_, io::Error>(0..5);
+
+    let times = timer.run(timer.throttle(source, Duration::from_secs(1))
+        .map(move |i| (i, t.now() - start))
+        .collect()).unwrap();
+
+    let expect: Vec<_> = (0..5).map(|i| (i, Duration::from_secs(i as u64))).collect();
+    assert_eq!(expect, times);
+}
+
+#[test]
+fn test_throttle_does_not_delay_spaced_items() {
+    let timer = mock::Timer::new();
+
+    let (tx, rx) = channel();
+    let mut s = support::task(timer.throttle(rx, Duration::from_secs(1)));
+
+    tx.unbounded_send(1).unwrap();
+    assert_eq!(Async::Ready(Some(1)), s.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(2));
+
+    tx.unbounded_send(2).unwrap();
+    assert_eq!(Async::Ready(Some(2)), s.poll_stream().unwrap());
+
+    tx.unbounded_send(3).unwrap();
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(s.is_notified());
+    assert_eq!(Async::Ready(Some(3)), s.poll_stream().unwrap());
+}
+
+#[test]
+fn test_rate_limit_allows_bursts() {
+    let timer = mock::Timer::new();
+    let start = timer.now();
+
+    let t = timer.clone();
+    let source = futures::stream::iter_ok::<_, io::Error>(0..7);
+
+    let times = timer.run(timer.rate_limit(source, 3, Duration::from_secs(10))
+        .map(move |_| (t.now() - start).as_secs())
+        .collect()).unwrap();
+
+    assert_eq!(vec![0, 0, 0, 10, 10, 10, 20], times);
+}
+
+#[test]
+fn test_throttle_very_long_period() {
+    let timer = mock::Timer::new();
+
+    let (tx, rx) = channel();
+    let mut s = support::task(timer.throttle(rx, Duration::from_secs(u64::max_value())));
+
+    tx.unbounded_send(1).unwrap();
+    tx.unbounded_send(2).unwrap();
+    assert_eq!(Async::Ready(Some(1)), s.poll_stream().unwrap());
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+
+    timer.advance(Duration::from_secs(10 * 365 * 86_400));
+    assert_eq!(Async::NotReady, s.poll_stream().unwrap());
+}
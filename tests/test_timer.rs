@@ -4,12 +4,14 @@ extern crate tokio_timer as timer;
 mod support;
 
 // use futures::*;
-use futures::{Future, Stream, Sink, Async};
+use futures::{future, Future, Stream, Sink, Async};
+use futures::executor::{self, Notify, NotifyHandle};
 use futures::sync::{oneshot, mpsc};
 use timer::*;
 use std::io;
 use std::time::*;
 use std::thread;
+use std::sync::{Arc, Mutex};
 
 #[test]
 fn test_immediate_sleep() {
@@ -57,6 +59,24 @@ fn test_setting_later_sleep_then_earlier_one() {
     t2.join().unwrap().assert_is_about(dur2);
 }
 
+#[test]
+fn test_reset_sleep() {
+    let timer = Timer::default();
+
+    let mut sleep = support::task(timer.sleep(Duration::from_millis(200)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    let dur = Duration::from_millis(500);
+
+    let elapsed = support::time(|| {
+        let when = timer.now() + dur;
+        sleep.get_mut().reset(when);
+        sleep.into_inner().wait().unwrap();
+    });
+
+    elapsed.assert_is_about(dur);
+}
+
 #[test]
 fn test_timer_with_looping_wheel() {
     let timer = timer::wheel()
@@ -77,6 +97,44 @@ fn test_timer_with_looping_wheel() {
     e2.assert_is_about(Duration::from_millis(800));
 }
 
+#[test]
+fn test_sleep_cascades_through_levels() {
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .num_slots(4)
+        .build();
+
+    let dur1 = Duration::from_millis(100);
+    let dur2 = Duration::from_millis(700);
+
+    let to1 = timer.sleep(dur1);
+    let to2 = timer.sleep(dur2);
+
+    let e1 = support::time(|| to1.wait().unwrap());
+    let e2 = support::time(|| to2.wait().unwrap());
+
+    e1.assert_is_about(dur1);
+    e2.assert_is_about(dur2 - dur1);
+}
+
+#[test]
+fn test_very_long_sleep_is_accepted() {
+    let timer = Timer::default();
+
+    let short = timer.sleep(Duration::from_millis(200));
+    let mut long = timer.sleep(Duration::from_secs(24 * 60 * 60));
+
+    future::lazy(|| {
+        assert!(long.poll().unwrap().is_not_ready());
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+
+    let e = support::time(|| short.wait().unwrap());
+    e.assert_is_about(Duration::from_millis(200));
+
+    assert!(!long.is_expired());
+}
+
 #[test]
 fn test_request_sleep_greater_than_max() {
     let timer = timer::wheel()
@@ -90,6 +148,130 @@ fn test_request_sleep_greater_than_max() {
     assert!(to.wait().is_ok());
 }
 
+#[test]
+fn test_sleep_reads_time_from_clock() {
+    struct ManualClock(Mutex<Instant>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    let timer = timer::wheel().clock(clock.clone()).build();
+    let dur = Duration::from_secs(60);
+
+    let mut sleep = timer.sleep(dur);
+
+    assert!(!sleep.is_expired());
+    assert_eq!(dur, sleep.remaining());
+
+    let now = {
+        let mut now = clock.0.lock().unwrap();
+        *now += dur;
+        *now
+    };
+
+    assert_eq!(now, timer.now());
+    assert!(sleep.is_expired());
+    assert_eq!(Duration::from_millis(0), sleep.remaining());
+    assert_eq!(Async::Ready(()), sleep.poll().unwrap());
+}
+
+#[test]
+fn test_shutdown_notifies_pending_sleeps() {
+    let timer = Timer::default();
+
+    let mut sleep = support::task(timer.sleep(Duration::from_secs(60)));
+    assert_eq!(Async::NotReady, sleep.poll().unwrap());
+
+    timer.shutdown().wait().unwrap();
+
+    assert!(sleep.is_notified());
+    assert_eq!(TimerError::Shutdown, sleep.poll().unwrap_err());
+
+    // New timeouts are rejected
+    let err = timer.timeout(future::empty::<(), io::Error>(), Duration::from_secs(1))
+        .wait()
+        .unwrap_err();
+
+    assert_eq!(io::ErrorKind::Other, err.kind());
+
+    // Expired sleeps still complete
+    assert_eq!(Async::Ready(()), timer.sleep(Duration::from_millis(0)).poll().unwrap());
+}
+
+/// Registers a sleep whose task panics when notified, which makes the timer
+/// thread panic.
+fn panic_timer_thread(timer: &Timer) {
+    panic_on_notify(timer, Duration::from_millis(200));
+}
+
+/// Registers a sleep of `dur` whose task panics when notified
+fn panic_on_notify(timer: &Timer, dur: Duration) {
+    struct Panic;
+
+    impl Notify for Panic {
+        fn notify(&self, _: usize) {
+            panic!("notify");
+        }
+    }
+
+    let mut sleep = executor::spawn(timer.sleep(dur));
+    let handle = NotifyHandle::from(Arc::new(Panic));
+
+    assert_eq!(Async::NotReady, sleep.poll_future_notify(&handle, 0).unwrap());
+
+    // Keep the sleep registered
+    ::std::mem::forget(sleep);
+}
+
+#[test]
+fn test_timer_thread_panic_poisons_timer() {
+    let timer = Timer::default();
+    let sleep = timer.sleep(Duration::from_secs(60));
+
+    panic_timer_thread(&timer);
+
+    assert_eq!(TimerError::Poisoned, sleep.wait().unwrap_err());
+    assert_eq!(TimerError::Poisoned, timer.sleep(Duration::from_secs(1)).wait().unwrap_err());
+}
+
+#[test]
+fn test_shutdown_completes_when_notifying_panics() {
+    let timer = Timer::default();
+    let sleep = timer.sleep(Duration::from_secs(60));
+
+    panic_on_notify(&timer, Duration::from_secs(60));
+
+    timer.shutdown().wait().unwrap();
+    assert_eq!(TimerError::Shutdown, sleep.wait().unwrap_err());
+}
+
+#[test]
+fn test_timer_thread_respawn_on_panic() {
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .respawn_on_panic(true)
+        .build();
+
+    let dur = Duration::from_millis(300);
+    let sleep = timer.sleep(dur);
+
+    let t = thread::spawn(move || {
+        support::time(|| sleep.wait().unwrap())
+    });
+
+    panic_timer_thread(&timer);
+
+    t.join().unwrap().assert_is_about(dur);
+
+    // The timer keeps working
+    let elapsed = support::time(|| timer.sleep(dur).wait().unwrap());
+    elapsed.assert_is_about(dur);
+}
+
 #[test]
 fn test_timeout_with_future_completes_first() {
     let timer = Timer::default();
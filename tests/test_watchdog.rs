@@ -0,0 +1,91 @@
+extern crate futures;
+extern crate tokio_timer as timer;
+
+mod support;
+
+use futures::{Async, Future};
+use timer::mock;
+use std::thread;
+use std::time::*;
+
+#[test]
+fn test_watchdog_fires_without_kicks() {
+    let timer = mock::Timer::new();
+    let mut watchdog = support::task(timer.watchdog(Duration::from_secs(10)));
+
+    assert_eq!(Async::NotReady, watchdog.poll().unwrap());
+
+    timer.advance(Duration::from_secs(9));
+    assert!(!watchdog.is_notified());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(watchdog.is_notified());
+    assert_eq!(Async::Ready(()), watchdog.poll().unwrap());
+}
+
+#[test]
+fn test_watchdog_kick_postpones() {
+    let timer = mock::Timer::new();
+    let mut watchdog = support::task(timer.watchdog(Duration::from_secs(10)));
+    let handle = watchdog.get_mut().handle();
+
+    assert_eq!(Async::NotReady, watchdog.poll().unwrap());
+
+    // Kicking does not wake the watchdog task
+    for _ in 0..8 {
+        timer.advance(Duration::from_secs(1));
+        handle.kick();
+        assert!(!watchdog.is_notified());
+    }
+
+    // The watchdog wakes up at the original deadline, and waits for the
+    // time remaining since the last kick.
+    timer.advance(Duration::from_secs(2));
+    assert!(watchdog.is_notified());
+    assert_eq!(Async::NotReady, watchdog.poll().unwrap());
+
+    timer.advance(Duration::from_secs(7));
+    assert!(!watchdog.is_notified());
+
+    timer.advance(Duration::from_secs(1));
+    assert!(watchdog.is_notified());
+    assert_eq!(Async::Ready(()), watchdog.poll().unwrap());
+}
+
+#[test]
+fn test_watchdog_very_long_duration() {
+    let timer = mock::Timer::new();
+    let mut watchdog = support::task(timer.watchdog(Duration::from_secs(u64::max_value())));
+
+    assert_eq!(Async::NotReady, watchdog.poll().unwrap());
+
+    watchdog.get_mut().kick();
+    timer.advance(Duration::from_secs(10 * 365 * 86_400));
+    assert!(!watchdog.is_notified());
+}
+
+#[test]
+fn test_watchdog_kick_from_other_thread() {
+    let timer = timer::wheel()
+        .tick_duration(Duration::from_millis(10))
+        .build();
+
+    let watchdog = timer.watchdog(Duration::from_millis(200));
+    let handle = watchdog.handle();
+
+    let start = Instant::now();
+
+    let th = thread::spawn(move || {
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(50));
+            handle.kick();
+        }
+    });
+
+    watchdog.wait().unwrap();
+    th.join().unwrap();
+
+    // Kicked for 500ms, then fires 200ms after the last kick
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(650), "elapsed = {:?}", elapsed);
+}